@@ -4,6 +4,7 @@ use std::sync::Arc;
 
 use audiopus::coder::Decoder;
 use audiopus::{Channels, SampleRate};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::Receiver;
 
@@ -11,12 +12,60 @@ use crate::audio_socket::AudioMessage;
 use crate::audio_socket::StreamStartMessage;
 use crate::audio_stream::AudioSource;
 use crate::gui::PlayerState;
+use crate::notifications::NotificationTap;
+use crate::recorder::RecorderTap;
 
 pub struct PlayingInfo {
     pub item: Option<StreamStartMessage>,
     pub buffering: bool,
 }
 
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TransitionMode {
+    /// Switch resources without any processing
+    None,
+    /// Fade out the old resource, then fade in the new one
+    Fade,
+    /// Overlap the end of the old resource with the start of the new one
+    Crossfade,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ResumeMode {
+    /// Drop the audio received while paused and continue with the live stream
+    Live,
+    /// Continue where playback was paused, as far as the pause buffer reaches back
+    TimeShift,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Transition {
+    pub mode: TransitionMode,
+    pub duration_ms: u32,
+}
+
+impl Default for Transition {
+    fn default() -> Self {
+        Transition {
+            mode: TransitionMode::None,
+            duration_ms: 1000,
+        }
+    }
+}
+
+impl Transition {
+    fn frames(&self) -> usize {
+        let frames = self.duration_ms as u64 * SAMPLE_RATE / 1000 / SAMPLES_PER_FRAME;
+        frames.max(1) as usize
+    }
+}
+
+/// A message taken from the buffer, audio frames stay in the history
+enum Taken {
+    Audio,
+    NewResource(StreamStartMessage),
+}
+
 pub struct AudioClient {
     decoder: Decoder,
     timestamp: u64,
@@ -24,18 +73,64 @@ pub struct AudioClient {
     buffering: bool,
     receiver: Receiver<AudioMessage>,
     context: Arc<PlayerState>,
+    recorder: RecorderTap,
+    notifications: Option<NotificationTap>,
+    /// Settings of the current transition, fixed once it started
+    transition: Option<Transition>,
+    /// Length of the running fade out, no longer than the old resource was buffered when it
+    /// started
+    fade_out: Option<usize>,
+    /// Frames left to fade in after a resource boundary and the length of the fade
+    fade_in: Option<(usize, usize)>,
+    /// Messages taken from the buffer, kept for rewinding
+    history: VecDeque<AudioMessage>,
+    /// Audio frames in `history`
+    history_frames: usize,
+    /// Resource and timestamp before the oldest message in `history`
+    history_start: (Option<StreamStartMessage>, u64),
+    /// Messages taken from the buffer, minus the ones rewound
+    taken: u64,
+    /// Highest `taken` so far, messages below it were played before
+    live_mark: u64,
+    /// The last message taken is played again after rewinding, it isn't passed to the recorder
+    /// and the notifications again
+    replaying: bool,
 }
 
 impl AudioClient {
-    pub fn new(receiver: Receiver<AudioMessage>, context: Arc<PlayerState>) -> Self {
-        AudioClient {
-            decoder: Decoder::new(SampleRate::Hz48000, Channels::Stereo).unwrap(),
+    pub fn new(
+        receiver: Receiver<AudioMessage>,
+        context: Arc<PlayerState>,
+        recorder: RecorderTap,
+    ) -> Self {
+        let mut client = AudioClient {
+            decoder: create_decoder(),
             receiver,
             timestamp: 0,
             buffer: VecDeque::with_capacity(50),
             buffering: true,
             context,
-        }
+            recorder,
+            notifications: None,
+            transition: None,
+            fade_out: None,
+            fade_in: None,
+            history: VecDeque::new(),
+            history_frames: 0,
+            history_start: (None, 0),
+            taken: 0,
+            live_mark: 0,
+            replaying: false,
+        };
+        // Nothing is buffered yet, the time until the first frame counts as buffering
+        client.set_context_buffering();
+        client
+    }
+
+    /// Reports every new resource to the desktop notifications
+    pub fn with_notifications(mut self, notifications: NotificationTap) -> Self {
+        self.notifications = Some(notifications);
+        self
     }
 }
 
@@ -43,6 +138,19 @@ pub const SAMPLES_PER_FRAME: u64 = 960;
 pub const SAMPLE_RATE: u64 = 48000;
 pub const TIME_BASE: u64 = 1000000;
 
+pub fn create_decoder() -> Decoder {
+    Decoder::new(SampleRate::Hz48000, Channels::Stereo).unwrap()
+}
+
+/// Decodes a single Opus packet into interleaved stereo samples
+pub fn decode_frame(decoder: &mut Decoder, data: &[u8]) -> audiopus::Result<Vec<f32>> {
+    let mut buffer = Vec::with_capacity(512 * 12);
+    buffer.resize(512 * 12, 0.0);
+    let res = decoder.decode_float(Some(data), buffer.as_mut_slice(), false)? * 2;
+    buffer.resize(res, 0.0);
+    Ok(buffer)
+}
+
 impl AudioClient {
     fn update_timestamp(&mut self, timestamp: u64) {
         self.timestamp = timestamp;
@@ -51,6 +159,7 @@ impl AudioClient {
 
     fn set_context_buffering(&mut self) {
         self.context.state().deref_mut().buffering = self.buffering;
+        self.context.statistics().set_buffering(self.buffering);
         if self.buffering {
             info!("Buffering");
         } else {
@@ -58,21 +167,40 @@ impl AudioClient {
         }
     }
 
-    fn decode(&mut self, data: Vec<u8>) -> Vec<f32> {
+    /// Decodes the audio frame taken last, it is the newest message in the history
+    fn decode(&mut self) -> Vec<f32> {
         self.update_timestamp(self.timestamp + SAMPLES_PER_FRAME);
-        let mut buffer = Vec::with_capacity(512 * 12);
-        buffer.resize(512 * 12, 0.0);
-        let res = self
-            .decoder
-            .decode_float(Some(data.as_slice()), buffer.as_mut_slice(), false)
-            .unwrap()
-            * 2;
-        buffer.resize(res, 0.0);
+        let data = match self.history.back() {
+            Some(AudioMessage::Audio(data)) => data.as_slice(),
+            _ => unreachable!("decoding without an audio frame"),
+        };
+        if !self.replaying {
+            self.recorder.opus(data);
+        }
+        let buffer = match decode_frame(&mut self.decoder, data) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                warn!("Failed to decode packet: {}", err);
+                self.context.statistics().add_decode_error();
+                // Keep the timing intact
+                vec![0.0; SAMPLES_PER_FRAME as usize * 2]
+            }
+        };
+        if !self.replaying {
+            self.recorder.pcm(buffer.as_slice());
+        }
         buffer
     }
 
     fn handle_new_resource(&mut self, message: StreamStartMessage) {
         let offset_sample = message.offset_samples;
+        if !self.replaying {
+            self.recorder.new_resource(message.name.as_str());
+            if let Some(notifications) = self.notifications.as_ref() {
+                notifications.track_changed(&message);
+            }
+            self.context.start_resource();
+        }
         *self.context.state() = PlayingInfo {
             item: Some(message),
             buffering: self.buffering,
@@ -80,26 +208,319 @@ impl AudioClient {
         self.update_timestamp(offset_sample);
     }
 
+    /// Jitter buffer statistics leave out rewound messages
+    fn update_buffer(&self) {
+        let replay = (self.live_mark - self.taken) as usize;
+        self.context.set_buffer(self.buffer.len(), replay);
+    }
+
+    /// Moves the next message from the buffer into the history. Audio frames stay there for
+    /// `decode`, only resources are copied out
+    fn take_message(&mut self) -> Option<Taken> {
+        let message = self.buffer.pop_front()?;
+        self.taken += 1;
+        self.replaying = self.taken <= self.live_mark;
+        self.live_mark = self.live_mark.max(self.taken);
+        let taken = match &message {
+            AudioMessage::Audio(_) => {
+                self.history_frames += 1;
+                Taken::Audio
+            }
+            AudioMessage::NewResource(info) => Taken::NewResource(info.clone()),
+        };
+        self.history.push_back(message);
+        // The message just taken is never trimmed, the history holds at least one frame
+        while self.history_frames > self.context.max_history() {
+            match self.history.pop_front() {
+                Some(AudioMessage::NewResource(info)) => {
+                    self.history_start = (Some(info.clone()), info.offset_samples)
+                }
+                Some(AudioMessage::Audio(_)) => {
+                    self.history_frames -= 1;
+                    self.history_start.1 += SAMPLES_PER_FRAME;
+                }
+                None => break,
+            }
+        }
+        Some(taken)
+    }
+
+    /// Returns the message taken last to the buffer
+    fn untake_message(&mut self) {
+        let message = match self.history.pop_back() {
+            Some(message) => message,
+            None => return,
+        };
+        if matches!(message, AudioMessage::Audio(_)) {
+            self.history_frames -= 1;
+        }
+        // It wasn't played, so it counts as live again
+        if !self.replaying {
+            self.live_mark -= 1;
+        }
+        self.taken -= 1;
+        self.buffer.push_front(message);
+    }
+
+    fn pop_message(&mut self) -> Option<Taken> {
+        let message = self.take_message()?;
+        self.update_buffer();
+        if self.buffer.is_empty() {
+            self.buffering = true;
+            self.set_context_buffering();
+        }
+        Some(message)
+    }
+
+    /// Number of audio frames in the buffer before the next resource starts and the number of
+    /// audio frames of the next resource following it
+    fn frames_around_boundary(&self) -> Option<(usize, usize)> {
+        let boundary = self
+            .buffer
+            .iter()
+            .position(|m| matches!(m, AudioMessage::NewResource(_)))?;
+        let after = self
+            .buffer
+            .iter()
+            .skip(boundary + 1)
+            .take_while(|m| matches!(m, AudioMessage::Audio(_)))
+            .count();
+        Some((boundary, after))
+    }
+
+    /// Decodes the next `count` audio frames, handling resource changes in between
+    fn decode_frames(&mut self, count: usize) -> Vec<f32> {
+        let mut result = Vec::new();
+        let mut decoded = 0;
+        while decoded < count {
+            match self.pop_message() {
+                None => break,
+                Some(Taken::NewResource(info)) => self.handle_new_resource(info),
+                Some(Taken::Audio) => {
+                    result.extend(self.decode());
+                    decoded += 1;
+                }
+            }
+        }
+        result
+    }
+
+    fn crossfade(&mut self, frames: usize) -> Vec<f32> {
+        let mut old = self.decode_frames(frames);
+        if let Some(Taken::NewResource(info)) = self.pop_message() {
+            self.handle_new_resource(info);
+        }
+        let new = self.decode_frames(frames);
+        let len = old.len().min(new.len()) / 2;
+        for (i, (a, b)) in old.chunks_exact_mut(2).zip(new.chunks_exact(2)).enumerate() {
+            // Equal power curves
+            let t = (i as f32 + 0.5) / len as f32 * std::f32::consts::FRAC_PI_2;
+            let (fade_in, fade_out) = t.sin_cos();
+            a[0] = a[0] * fade_out + b[0] * fade_in;
+            a[1] = a[1] * fade_out + b[1] * fade_in;
+        }
+        old
+    }
+
+    /// Linearly ramps the gain from `from` to `to` over the interleaved stereo samples
+    fn apply_ramp(samples: &mut [f32], from: f32, to: f32) {
+        let frames = (samples.len() / 2).max(1) as f32;
+        for (i, frame) in samples.chunks_exact_mut(2).enumerate() {
+            let gain = from + (to - from) * (i as f32 + 0.5) / frames;
+            frame[0] *= gain;
+            frame[1] *= gain;
+        }
+    }
+
+    fn current_transition(&mut self) -> Transition {
+        if let Some(transition) = self.transition {
+            return transition;
+        }
+        match self.context.try_transition() {
+            Some(transition) => {
+                self.transition = Some(transition);
+                transition
+            }
+            // The gui is changing the settings, don't wait for it and try again next frame
+            None => Transition {
+                mode: TransitionMode::None,
+                ..Transition::default()
+            },
+        }
+    }
+
+    /// Audio frames buffered beyond the target, including the frame taken last
+    fn surplus_frames(&self) -> usize {
+        (self.buffer.len() + 1).saturating_sub(self.context.target_buffer())
+    }
+
+    fn decode_with_transition(&mut self) -> Vec<f32> {
+        match self.frames_around_boundary() {
+            Some((remaining, after)) => {
+                let transition = self.current_transition();
+                let total = transition.frames();
+                // Frames of the old resource including this one
+                let old_frames = remaining + 1;
+                if old_frames <= total {
+                    match transition.mode {
+                        // Switching to a crossfade in the middle of a fade would jump back up. The
+                        // overlap plays two frames at once, it may only use the buffer beyond the
+                        // target or a live stream runs dry
+                        TransitionMode::Crossfade
+                            if self.fade_out.is_none()
+                                && old_frames <= after
+                                && old_frames <= self.surplus_frames() =>
+                        {
+                            self.untake_message();
+                            return self.crossfade(old_frames);
+                        }
+                        // Fall back to fading if not enough is buffered
+                        TransitionMode::Fade | TransitionMode::Crossfade => {
+                            let mut samples = self.decode();
+                            // Starts at full volume even if the boundary arrived late
+                            let length = *self.fade_out.get_or_insert(old_frames);
+                            let from = old_frames as f32 / length as f32;
+                            let to = remaining as f32 / length as f32;
+                            Self::apply_ramp(samples.as_mut_slice(), from, to);
+                            if remaining == 0 {
+                                self.fade_out = None;
+                                self.fade_in = Some((total, total));
+                            }
+                            return samples;
+                        }
+                        TransitionMode::None => (),
+                    }
+                }
+            }
+            None => {
+                self.transition = None;
+                self.fade_out = None;
+            }
+        }
+
+        let mut samples = self.decode();
+        if let Some((remaining, total)) = self.fade_in {
+            let from = (total - remaining) as f32 / total as f32;
+            let to = (total - remaining + 1) as f32 / total as f32;
+            Self::apply_ramp(samples.as_mut_slice(), from, to);
+            self.fade_in = if remaining > 1 {
+                Some((remaining - 1, total))
+            } else {
+                None
+            };
+        }
+        samples
+    }
+
     fn decode_one(&mut self) -> Option<Vec<f32>> {
         if self.buffering {
             return None;
         }
         loop {
-            match self.buffer.pop_front() {
-                None => return None,
+            match self.pop_message()? {
+                Taken::NewResource(info) => self.handle_new_resource(info),
+                Taken::Audio => return Some(self.decode_with_transition()),
+            }
+        }
+    }
+
+    /// Takes the next message without playing it, keeping the current resource and timestamp
+    /// up to date. Returns whether it was an audio frame, `None` if the buffer is empty
+    fn skip_message(&mut self) -> Option<bool> {
+        match self.take_message()? {
+            Taken::NewResource(info) => {
+                self.handle_new_resource(info);
+                Some(false)
+            }
+            Taken::Audio => {
+                self.update_timestamp(self.timestamp + SAMPLES_PER_FRAME);
+                Some(true)
+            }
+        }
+    }
+
+    /// Skips up to `frames` audio frames without playing them
+    fn skip(&mut self, frames: usize) {
+        let mut skipped = 0;
+        while skipped < frames {
+            match self.skip_message() {
+                Some(true) => skipped += 1,
+                Some(false) => (),
+                None => break,
+            }
+        }
+        self.update_buffer();
+        self.reset_decoding();
+    }
+
+    /// Drops the oldest messages until at most `len` are buffered
+    fn drop_backlog(&mut self, len: usize) {
+        if self.buffer.len() <= len {
+            return;
+        }
+        while self.buffer.len() > len && self.skip_message().is_some() {}
+        self.update_buffer();
+        self.reset_decoding();
+    }
+
+    /// Moves up to `frames` audio frames from the history back into the buffer and restores
+    /// the resource and timestamp at the new position
+    fn rewind(&mut self, frames: usize) {
+        let mut rewound = 0;
+        while rewound < frames {
+            match self.history.pop_back() {
                 Some(message) => {
-                    self.context.set_buffer(self.buffer.len());
-                    if self.buffer.is_empty() {
-                        self.buffering = true;
-                        self.set_context_buffering();
-                    }
-                    match message {
-                        AudioMessage::NewResource(info) => self.handle_new_resource(info),
-                        AudioMessage::Audio(data) => return Some(self.decode(data)),
+                    if matches!(message, AudioMessage::Audio(_)) {
+                        rewound += 1;
+                        self.history_frames -= 1;
                     }
+                    self.taken -= 1;
+                    self.buffer.push_front(message);
+                }
+                None => break,
+            }
+        }
+        let (mut item, mut timestamp) = self.history_start.clone();
+        for message in &self.history {
+            match message {
+                AudioMessage::NewResource(info) => {
+                    timestamp = info.offset_samples;
+                    item = Some(info.clone());
                 }
+                AudioMessage::Audio(_) => timestamp += SAMPLES_PER_FRAME,
             }
         }
+        *self.context.state() = PlayingInfo {
+            item,
+            buffering: self.buffering,
+        };
+        self.update_timestamp(timestamp);
+        self.update_buffer();
+        self.reset_decoding();
+    }
+
+    /// Seeks to `timestamp` in the current resource, as far as history and buffer reach
+    fn seek(&mut self, timestamp: u64) {
+        if timestamp < self.timestamp {
+            let frames = (self.timestamp - timestamp) / SAMPLES_PER_FRAME;
+            self.rewind(frames as usize);
+        } else {
+            // Never skip into the jitter buffer
+            let frames = (timestamp - self.timestamp) / SAMPLES_PER_FRAME;
+            let available = self
+                .buffer
+                .len()
+                .saturating_sub(self.context.target_buffer());
+            self.skip((frames as usize).min(available));
+        }
+    }
+
+    /// Skipped packets break the decoder state and any running transition
+    fn reset_decoding(&mut self) {
+        self.decoder = create_decoder();
+        self.transition = None;
+        self.fade_out = None;
+        self.fade_in = None;
     }
 
     fn receive_all(&mut self) {
@@ -107,7 +528,7 @@ impl AudioClient {
             match self.receiver.try_recv() {
                 Ok(m) => {
                     self.buffer.push_back(m);
-                    self.context.set_buffer(self.buffer.len());
+                    self.update_buffer();
                     if self.buffering {
                         self.buffering = self.buffer.len() < self.context.target_buffer();
                         if !self.buffering {
@@ -129,8 +550,23 @@ impl Iterator for AudioClient {
 
     fn next(&mut self) -> Option<Vec<f32>> {
         self.receive_all();
+        if self.context.is_paused() {
+            // Keep receiving so the connection stays alive, but bound the backlog
+            self.drop_backlog(self.context.max_pause_buffer());
+            return Some(vec![0.0; SAMPLES_PER_FRAME as usize * 2]);
+        }
+        if self.context.take_live_resume() {
+            self.drop_backlog(self.context.target_buffer());
+        }
+        if let Some(timestamp) = self.context.take_seek() {
+            self.seek(timestamp);
+        }
         self.decode_one()
     }
 }
 
-impl AudioSource for AudioClient {}
+impl AudioSource for AudioClient {
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.timestamp)
+    }
+}
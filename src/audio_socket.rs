@@ -1,16 +1,19 @@
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use tokio::stream::StreamExt;
 use tokio::sync::mpsc::Sender;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
 
+use crate::session::SharedSessionWriter;
+use crate::statistics::Statistics;
 use crate::token::*;
 
 pub type SocketToken = Token<CancelableToken<CompletableToken<ValueToken<()>>>>;
 
+#[derive(Clone)]
 pub enum AudioMessage {
     NewResource(StreamStartMessage),
     Audio(Vec<u8>),
@@ -21,6 +24,8 @@ pub struct AudioSocket {
     token: SocketToken,
     updates: Arc<Mutex<State>>,
     output: Sender<AudioMessage>,
+    statistics: Arc<Statistics>,
+    session: Option<SharedSessionWriter>,
 }
 
 impl AudioSocket {
@@ -29,12 +34,16 @@ impl AudioSocket {
         token: SocketToken,
         updates: Arc<Mutex<State>>,
         output: Sender<AudioMessage>,
+        statistics: Arc<Statistics>,
+        session: Option<SharedSessionWriter>,
     ) -> Self {
         AudioSocket {
             address,
             token,
             updates,
             output,
+            statistics,
+            session,
         }
     }
 }
@@ -46,7 +55,26 @@ pub enum State {
     Disconnecting,
 }
 
-#[derive(Deserialize)]
+impl State {
+    pub const ALL: [State; 4] = [
+        State::None,
+        State::Connecting,
+        State::Connected,
+        State::Disconnecting,
+    ];
+
+    /// Name used by the control API and the metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::None => "none",
+            State::Connecting => "connecting",
+            State::Connected => "connected",
+            State::Disconnecting => "disconnecting",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub struct StreamStartMessage {
     /// Offset in samples from `start_timestamp_us`
     pub offset_samples: u64,
@@ -59,6 +87,59 @@ pub struct StreamStartMessage {
     pub duration_us: Option<u64>,
 
     pub name: String,
+
+    /// Shared so the gui can keep it without holding the player state
+    #[serde(flatten)]
+    pub metadata: Arc<TrackMetadata>,
+}
+
+/// Optional details about a resource, older servers don't send them
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TrackMetadata {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Page the resource was taken from
+    pub source_url: Option<String>,
+    /// User who queued the resource
+    pub requester: Option<String>,
+    pub thumbnail_url: Option<String>,
+    /// Encoded PNG or JPEG image, base64 in the message
+    #[serde(deserialize_with = "deserialize_thumbnail")]
+    pub thumbnail: Option<Arc<[u8]>>,
+    pub tags: Vec<String>,
+}
+
+/// Ignores invalid thumbnails instead of dropping the whole message
+fn deserialize_thumbnail<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Arc<[u8]>>, D::Error> {
+    let encoded = match Option::<String>::deserialize(deserializer)? {
+        Some(encoded) => encoded,
+        None => return Ok(None),
+    };
+    match base64::decode(&encoded) {
+        Ok(data) => Ok(Some(data.into())),
+        Err(err) => {
+            warn!("Invalid thumbnail: {}", err);
+            Ok(None)
+        }
+    }
+}
+
+/// Converts a server message for the client, returns `None` for messages that are ignored
+pub fn parse_message(message: Message) -> Option<AudioMessage> {
+    match message {
+        Message::Text(text) => match serde_json::from_str::<StreamStartMessage>(&text) {
+            Ok(message) => Some(AudioMessage::NewResource(message)),
+            Err(err) => {
+                warn!("Invalid message, failed to parse: {}", err);
+                None
+            }
+        },
+        Message::Binary(data) => Some(AudioMessage::Audio(data)),
+        _ => None,
+    }
 }
 
 enum HandleMessageResult {
@@ -67,18 +148,28 @@ enum HandleMessageResult {
 }
 
 impl AudioSocket {
+    fn record(&mut self, message: &Message) {
+        let res = match self.session.as_ref() {
+            Some(session) => session.lock().unwrap().write(message),
+            None => return,
+        };
+        if let Err(err) = res {
+            warn!("Failed to record session, stopping: {}", err);
+            self.session = None;
+        }
+    }
+
     async fn handle_message(&mut self, message: Message) -> HandleMessageResult {
-        let send_res = match message {
-            Message::Text(text) => match serde_json::from_str::<StreamStartMessage>(&text) {
-                Ok(message) => self.output.send(AudioMessage::NewResource(message)).await,
-                Err(err) => {
-                    warn!("Invalid message, failed to parse: {}", err);
-                    return HandleMessageResult::Ok;
-                }
-            },
-            Message::Binary(data) => self.output.send(AudioMessage::Audio(data)).await,
-            Message::Close(_) => return HandleMessageResult::Exit,
-            _ => return HandleMessageResult::Ok,
+        if message.is_close() {
+            return HandleMessageResult::Exit;
+        }
+        self.record(&message);
+        if let Message::Binary(data) = &message {
+            self.statistics.add_packet(data.len());
+        }
+        let send_res = match parse_message(message) {
+            Some(message) => self.output.send(message).await,
+            None => return HandleMessageResult::Ok,
         };
 
         match send_res {
@@ -94,10 +185,15 @@ impl AudioSocket {
         let token = TokenCompleter::new(self.token.clone());
         *self.updates.lock().unwrap() = State::Connecting;
 
-        let (mut stream, _) = connect_async(&self.address)
-            .await
-            .expect("Failed to connect");
+        let mut stream = match connect_async(&self.address).await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("Failed to connect to {}: {}", self.address, err);
+                return;
+            }
+        };
         *self.updates.lock().unwrap() = State::Connected;
+        self.statistics.add_connection();
         while !token.token().is_canceled() {
             match tokio::time::timeout(Duration::from_millis(20), stream.next()).await {
                 Ok(msg) => match msg {
@@ -121,9 +217,9 @@ impl AudioSocket {
         }
 
         *self.updates.lock().unwrap() = State::Disconnecting;
-        stream
-            .close(None)
-            .await
-            .expect("Failed to close connection");
+        // Fails if the server already closed the connection
+        if let Err(err) = stream.close(None).await {
+            info!("Failed to close connection: {}", err);
+        }
     }
 }
@@ -1,14 +1,36 @@
-use cpal::traits::{DeviceTrait, HostTrait};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::Stream;
 
+use crate::audio_client::SAMPLE_RATE;
+use crate::effects::EffectChain;
+use crate::playback_clock::PlaybackClock;
+use crate::statistics::Statistics;
+
 struct Chunk {
     data: Vec<f32>,
     offset: usize,
+    /// Positions in the resource at the start and end of the chunk
+    timestamps: Option<(u64, u64)>,
 }
 
 impl Chunk {
-    pub fn new(data: Vec<f32>) -> Self {
-        Chunk { data, offset: 0 }
+    pub fn new(data: Vec<f32>, timestamps: Option<(u64, u64)>) -> Self {
+        Chunk {
+            data,
+            offset: 0,
+            timestamps,
+        }
+    }
+
+    /// Positions in the resource of the interleaved stereo samples `from..to`
+    fn positions(&self, from: usize, to: usize) -> Option<(u64, u64)> {
+        let (start, end) = self.timestamps?;
+        let len = self.data.len().max(1) as u64;
+        let position = |offset: usize| start + (end - start) * offset as u64 / len;
+        Some((position(from), position(to)))
     }
 
     pub fn remaining_slice(&self) -> &[f32] {
@@ -17,60 +39,164 @@ impl Chunk {
 }
 
 /// Essentially an endless iterator, returning None means currently no data
-pub trait AudioSource: Iterator<Item = Vec<f32>> + Send {}
+pub trait AudioSource: Iterator<Item = Vec<f32>> + Send {
+    /// Position in the current resource at the end of the last chunk, in samples
+    fn timestamp(&self) -> Option<u64> {
+        None
+    }
+}
 
-pub fn create_stream<F: AudioSource + 'static>(mut source: F) -> Stream {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("failed to find a default output device");
-    let config = device.default_output_config().unwrap();
-    info!("Stream config: {:?}", config);
+/// Splits the chunks of an `AudioSource` into the buffers requested by an output
+pub struct SourceReader {
+    source: Box<dyn AudioSource>,
+    effects: EffectChain,
+    current_chunk: Option<Chunk>,
+    last_keep_up: bool,
+    statistics: Arc<Statistics>,
+    clock: Option<Arc<PlaybackClock>>,
+    /// Time until the samples written in a callback are heard
+    latency: Duration,
+    /// Source timestamp at the end of the last chunk
+    last_timestamp: Option<u64>,
+}
 
-    let err_fn = |err| warn!("an error occurred on stream: {}", err);
+impl SourceReader {
+    pub fn new<F: AudioSource + 'static>(
+        source: F,
+        effects: EffectChain,
+        statistics: Arc<Statistics>,
+    ) -> Self {
+        SourceReader {
+            source: Box::new(source),
+            effects,
+            current_chunk: None,
+            last_keep_up: true,
+            statistics,
+            clock: None,
+            latency: Duration::from_secs(0),
+            last_timestamp: None,
+        }
+    }
+
+    /// Reports the played positions of the source to `clock`
+    pub fn with_clock(mut self, clock: Arc<PlaybackClock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
 
-    let mut current_chunk = None;
+    /// Sets the output latency reported by the device
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = latency;
+    }
 
-    let mut last_keep_up = true;
+    /// Start and end of a new chunk in the resource, following the previous chunk if the
+    /// source position didn't jump
+    fn chunk_timestamps(&mut self, chunk: &[f32]) -> Option<(u64, u64)> {
+        let end = self.source.timestamp()?;
+        let frames = (chunk.len() / 2) as u64;
+        let start = match self.last_timestamp.replace(end) {
+            Some(last) if last <= end && end - last <= frames => last,
+            _ => end.saturating_sub(frames),
+        };
+        Some((start, end))
+    }
 
-    // let callback = ;
+    pub fn statistics(&self) -> &Arc<Statistics> {
+        &self.statistics
+    }
 
-    device
-        .build_output_stream(
-            &config.into(),
-            move |mut data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                last_keep_up = loop {
-                    if data.is_empty() {
-                        break true;
+    /// Fills `data` completely, padding with silence and returning false if the source can't
+    /// keep up
+    pub fn fill(&mut self, mut data: &mut [f32]) -> bool {
+        let now = Instant::now();
+        let mut written = 0;
+        self.last_keep_up = loop {
+            if data.is_empty() {
+                break true;
+            }
+            let mut chunk = match self.current_chunk.take() {
+                None => match self.source.next() {
+                    Some(mut chunk) => {
+                        self.effects.process(chunk.as_mut_slice());
+                        let timestamps = self.chunk_timestamps(&chunk);
+                        Chunk::new(chunk, timestamps)
                     }
-                    let mut chunk = match current_chunk.take() {
-                        None => match source.next() {
-                            Some(chunk) => Chunk::new(chunk),
-                            None => {
-                                if last_keep_up {
-                                    warn!("Can't keep up");
-                                }
-                                for x in data {
-                                    *x = 0.0;
-                                }
-                                break false;
-                            }
-                        },
-                        Some(chunk) => chunk,
-                    };
-
-                    let remaining_data = chunk.remaining_slice();
-                    let split_point = remaining_data.len().min(data.len());
-                    let (a, new_data) = data.split_at_mut(split_point);
-                    a.copy_from_slice(&remaining_data[..split_point]);
-                    if split_point < remaining_data.len() {
-                        chunk.offset += split_point;
-                        current_chunk = Some(chunk);
+                    None => {
+                        if self.last_keep_up {
+                            warn!("Can't keep up");
+                            self.statistics.add_underrun();
+                            self.effects.reset();
+                        }
+                        for x in data {
+                            *x = 0.0;
+                        }
+                        break false;
                     }
-                    data = new_data;
-                };
-            },
-            err_fn,
-        )
-        .unwrap()
+                },
+                Some(chunk) => chunk,
+            };
+
+            let remaining_data = chunk.remaining_slice();
+            let split_point = remaining_data.len().min(data.len());
+            let (a, new_data) = data.split_at_mut(split_point);
+            a.copy_from_slice(&remaining_data[..split_point]);
+            if let Some(clock) = self.clock.as_ref() {
+                if let Some((start, end)) =
+                    chunk.positions(chunk.offset, chunk.offset + split_point)
+                {
+                    let offset = Duration::from_secs_f64((written / 2) as f64 / SAMPLE_RATE as f64);
+                    let frames = (split_point / 2) as u64;
+                    clock.push(now, now + self.latency + offset, start, end, frames);
+                }
+            }
+            written += split_point;
+            if split_point < remaining_data.len() {
+                chunk.offset += split_point;
+                self.current_chunk = Some(chunk);
+            }
+            data = new_data;
+        };
+        self.last_keep_up
+    }
+}
+
+/// Destination of the audio, pulling samples from a `SourceReader` at its own pace
+pub trait OutputSink {
+    /// Starts pulling samples from `reader`
+    fn start(&mut self, reader: SourceReader);
+}
+
+/// Plays the audio on the default output device
+#[derive(Default)]
+pub struct CpalSink {
+    stream: Option<Stream>,
+}
+
+impl OutputSink for CpalSink {
+    fn start(&mut self, mut reader: SourceReader) {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .expect("failed to find a default output device");
+        let config = device.default_output_config().unwrap();
+        info!("Stream config: {:?}", config);
+
+        let err_fn = |err| warn!("an error occurred on stream: {}", err);
+
+        let stream = device
+            .build_output_stream(
+                &config.into(),
+                move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                    let timestamp = info.timestamp();
+                    if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                        reader.set_latency(latency);
+                    }
+                    reader.fill(data);
+                },
+                err_fn,
+            )
+            .unwrap();
+        stream.play().unwrap();
+        self.stream = Some(stream);
+    }
 }
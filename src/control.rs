@@ -0,0 +1,230 @@
+//! HTTP/JSON API for scripting the client
+//!
+//! `POST /connect` and `POST /disconnect` control the connection, `POST /pause` and
+//! `POST /resume` pause playback while staying connected, `PUT /volume` takes
+//! `{"volume": 0.5}`, `PUT /target_buffer` takes `{"target_buffer_ms": 1000}` and
+//! `GET /now_playing` returns the current state.
+//!
+//! Requests other than `GET` need a `Content-Type: application/json` header, which web pages
+//! can't send cross-site without a CORS preflight that is never answered.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::audio_client::{SAMPLES_PER_FRAME, SAMPLE_RATE, TIME_BASE};
+use crate::audio_socket::State;
+use crate::effects::{db_to_linear, GainControl};
+use crate::gui::PlayerState;
+use crate::now_playing::{self, ResourceTime};
+
+/// Commands applied by the gui on the next frame
+pub enum ControlCommand {
+    Connect,
+    Disconnect,
+    Pause,
+    /// Resumes with the resume mode from the settings
+    Resume,
+    /// Master volume between 0 and 1
+    SetVolume(f32),
+}
+
+#[derive(Deserialize)]
+struct VolumeRequest {
+    volume: f32,
+}
+
+/// Largest request body read, larger requests are refused
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Range accepted for the target buffer, from one frame up to ten seconds
+const MIN_TARGET_BUFFER_MS: u64 = 20;
+const MAX_TARGET_BUFFER_MS: u64 = 10_000;
+
+#[derive(Deserialize)]
+struct TargetBufferRequest {
+    target_buffer_ms: u64,
+}
+
+#[derive(Serialize)]
+struct NowPlaying {
+    connection: &'static str,
+    title: Option<String>,
+    position_s: f64,
+    duration_s: Option<f64>,
+    buffering: bool,
+    paused: bool,
+    buffer_ms: u64,
+    target_buffer_ms: u64,
+    volume: f32,
+}
+
+/// Shared state used by the request handlers
+#[derive(Clone)]
+pub struct ControlApi {
+    pub commands: Sender<ControlCommand>,
+    pub player_state: Arc<PlayerState>,
+    pub socket_state: Arc<Mutex<State>>,
+    pub volume: Arc<GainControl>,
+}
+
+fn frames_to_ms(frames: usize) -> u64 {
+    frames as u64 * SAMPLES_PER_FRAME * 1000 / SAMPLE_RATE
+}
+
+fn json_response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, serde_json::json!({ "error": message }).to_string())
+}
+
+fn is_json(request: &Request<Body>) -> bool {
+    let mime = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next());
+    matches!(mime, Some(mime) if mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Reads the body up to `MAX_BODY_LEN`, the error is the response to send instead
+async fn read_body(request: Request<Body>) -> Result<Vec<u8>, Response<Body>> {
+    let too_large = || error(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large");
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if matches!(content_length, Some(len) if len > MAX_BODY_LEN as u64) {
+        return Err(too_large());
+    }
+    let mut body = request.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| error(StatusCode::BAD_REQUEST, "Failed to read body"))?;
+        // Chunked bodies have no length up front
+        if data.len() + chunk.len() > MAX_BODY_LEN {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+impl ControlApi {
+    fn now_playing(&self) -> NowPlaying {
+        let connection = self.socket_state.lock().unwrap().as_str();
+        let info = self.player_state.state();
+        let item = info.item.as_ref();
+        let position_us = now_playing::position_us(item, self.player_state.position());
+        NowPlaying {
+            connection,
+            title: item.map(|item| item.name.clone()),
+            position_s: position_us as f64 / TIME_BASE as f64,
+            duration_s: item
+                .and_then(|item| ResourceTime::new(item).duration_us)
+                .map(|duration| duration as f64 / TIME_BASE as f64),
+            buffering: info.buffering,
+            paused: self.player_state.is_paused(),
+            buffer_ms: frames_to_ms(self.player_state.buffer()),
+            target_buffer_ms: frames_to_ms(self.player_state.target_buffer()),
+            volume: db_to_linear(self.volume.gain_db()),
+        }
+    }
+
+    fn send(&self, command: ControlCommand) -> Response<Body> {
+        match self.commands.send(command) {
+            Ok(()) => json_response(StatusCode::ACCEPTED, "{}".into()),
+            Err(_) => error(StatusCode::SERVICE_UNAVAILABLE, "Client is shutting down"),
+        }
+    }
+
+    async fn handle(self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let path = request.uri().path().to_owned();
+        if method != Method::GET && !is_json(&request) {
+            return error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Content-Type must be application/json",
+            );
+        }
+        let body = match read_body(request).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        match (method, path.as_str()) {
+            (Method::GET, "/now_playing") => match serde_json::to_string(&self.now_playing()) {
+                Ok(json) => json_response(StatusCode::OK, json),
+                Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to serialize"),
+            },
+            (Method::POST, "/connect") => self.send(ControlCommand::Connect),
+            (Method::POST, "/disconnect") => self.send(ControlCommand::Disconnect),
+            (Method::POST, "/pause") => self.send(ControlCommand::Pause),
+            (Method::POST, "/resume") => self.send(ControlCommand::Resume),
+            (Method::PUT, "/volume") => match serde_json::from_slice::<VolumeRequest>(&body) {
+                Ok(request) if (0.0..=1.0).contains(&request.volume) => {
+                    self.send(ControlCommand::SetVolume(request.volume))
+                }
+                Ok(_) => error(StatusCode::BAD_REQUEST, "Volume must be between 0 and 1"),
+                Err(err) => error(StatusCode::BAD_REQUEST, &err.to_string()),
+            },
+            (Method::PUT, "/target_buffer") => {
+                match serde_json::from_slice::<TargetBufferRequest>(&body) {
+                    Ok(request)
+                        if (MIN_TARGET_BUFFER_MS..=MAX_TARGET_BUFFER_MS)
+                            .contains(&request.target_buffer_ms) =>
+                    {
+                        let frames =
+                            request.target_buffer_ms * SAMPLE_RATE / 1000 / SAMPLES_PER_FRAME;
+                        self.player_state.set_target_buffer(frames as usize);
+                        json_response(StatusCode::OK, "{}".into())
+                    }
+                    Ok(_) => error(
+                        StatusCode::BAD_REQUEST,
+                        &format!(
+                            "Target buffer must be between {} and {} ms",
+                            MIN_TARGET_BUFFER_MS, MAX_TARGET_BUFFER_MS
+                        ),
+                    ),
+                    Err(err) => error(StatusCode::BAD_REQUEST, &err.to_string()),
+                }
+            }
+            _ => error(StatusCode::NOT_FOUND, "Not found"),
+        }
+    }
+}
+
+/// Starts serving the API on `address`, returns the bound address
+pub fn start(address: SocketAddr, api: ControlApi) -> hyper::Result<SocketAddr> {
+    let make_service = make_service_fn(move |_| {
+        let api = api.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let api = api.clone();
+                async move { Ok::<_, Infallible>(api.handle(request).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&address)?.serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            warn!("Control server failed: {}", err);
+        }
+    });
+    Ok(address)
+}
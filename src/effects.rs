@@ -0,0 +1,364 @@
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::atomic::{AtomicU32, AtomicUsize};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::equalizer::{Equalizer, EqualizerControl};
+
+/// A processing stage operating on interleaved stereo samples
+pub trait AudioEffect: Send {
+    /// Processes the samples in place
+    fn process(&mut self, samples: &mut [f32]);
+
+    /// Clears any internal state, called on discontinuities in the stream
+    fn reset(&mut self);
+
+    /// Latency introduced by this effect in frames
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EffectKind {
+    Equalizer,
+    Gain,
+}
+
+impl EffectKind {
+    pub const ALL: [EffectKind; 2] = [EffectKind::Equalizer, EffectKind::Gain];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EffectKind::Equalizer => "Equalizer",
+            EffectKind::Gain => "Gain",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EffectConfig {
+    pub kind: EffectKind,
+    pub enabled: bool,
+    /// Gain of this instance, for Gain effects
+    #[serde(default)]
+    pub gain_db: f32,
+}
+
+impl EffectConfig {
+    pub fn new(kind: EffectKind) -> Self {
+        EffectConfig {
+            kind,
+            enabled: true,
+            gain_db: 0.0,
+        }
+    }
+}
+
+pub fn default_effects() -> Vec<EffectConfig> {
+    vec![EffectConfig {
+        enabled: false,
+        ..EffectConfig::new(EffectKind::Equalizer)
+    }]
+}
+
+/// Parameters shared between the gui and the audio thread. There is a single equalizer, the
+/// equalizer window edits it
+pub struct EffectControls {
+    pub equalizer: Arc<EqualizerControl>,
+    /// Master volume, applied after all effects
+    pub volume: Arc<GainControl>,
+}
+
+/// An effect ready to be inserted into the chain
+pub struct NewEffect {
+    pub effect: Box<dyn AudioEffect>,
+    /// Control of this instance, for Gain effects
+    pub gain: Option<Arc<GainControl>>,
+}
+
+impl EffectControls {
+    pub fn create(&self, config: &EffectConfig) -> NewEffect {
+        match config.kind {
+            EffectKind::Equalizer => NewEffect {
+                effect: Box::new(Equalizer::new(self.equalizer.clone())),
+                gain: None,
+            },
+            EffectKind::Gain => {
+                let gain = Arc::new(GainControl::new(config.gain_db));
+                NewEffect {
+                    effect: Box::new(Gain::new(gain.clone())),
+                    gain: Some(gain),
+                }
+            }
+        }
+    }
+}
+
+pub struct GainControl(AtomicU32);
+
+impl GainControl {
+    pub fn new(gain_db: f32) -> Self {
+        GainControl(AtomicU32::new(gain_db.to_bits()))
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        f32::from_bits(self.0.load(Acquire))
+    }
+
+    pub fn set_gain_db(&self, gain_db: f32) {
+        self.0.store(gain_db.to_bits(), Release);
+    }
+}
+
+pub struct Gain {
+    control: Arc<GainControl>,
+    current: f32,
+}
+
+impl Gain {
+    pub fn new(control: Arc<GainControl>) -> Self {
+        let current = db_to_linear(control.gain_db());
+        Gain { control, current }
+    }
+}
+
+pub fn db_to_linear(gain_db: f32) -> f32 {
+    10f32.powf(gain_db / 20.0)
+}
+
+/// Converts an amplitude factor to dB, zero results in negative infinity
+pub fn linear_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+impl AudioEffect for Gain {
+    fn process(&mut self, samples: &mut [f32]) {
+        let target = db_to_linear(self.control.gain_db());
+        let frames = (samples.len() / 2).max(1);
+        // Ramp towards the new gain over one chunk to avoid zipper noise
+        let step = (target - self.current) / frames as f32;
+        for frame in samples.chunks_exact_mut(2) {
+            self.current += step;
+            frame[0] *= self.current;
+            frame[1] *= self.current;
+        }
+        self.current = target;
+    }
+
+    fn reset(&mut self) {
+        self.current = db_to_linear(self.control.gain_db());
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct EffectId(u32);
+
+enum ChainCommand {
+    Insert(usize, EffectId, Box<dyn AudioEffect>),
+    Remove(EffectId),
+    SetEnabled(EffectId, bool),
+    Move(EffectId, usize),
+}
+
+struct Slot {
+    id: EffectId,
+    effect: Box<dyn AudioEffect>,
+    enabled: bool,
+    removed: bool,
+    /// Current amount of processed signal in the output
+    mix: f32,
+}
+
+/// Effects a chain holds at most
+pub const MAX_EFFECTS: usize = 16;
+/// Slots allocated up front, so inserting effects doesn't allocate on the audio thread. Leaves
+/// room for removed effects that are still fading out
+const SLOT_CAPACITY: usize = MAX_EFFECTS * 2;
+/// Samples crossfaded at once when toggling an effect, the dry signal is kept on the stack
+const DRY_BLOCK: usize = 256;
+
+/// Ordered list of effects, owned by the output callback. It never allocates or frees while
+/// processing, removed effects are sent back to the gui thread
+pub struct EffectChain {
+    slots: Vec<Slot>,
+    volume: Gain,
+    commands: Receiver<ChainCommand>,
+    removed: Sender<Box<dyn AudioEffect>>,
+    latency: Arc<AtomicUsize>,
+}
+
+/// Gui side of an `EffectChain`, changes are applied by the audio thread on the next chunk
+pub struct EffectChainHandle {
+    commands: Sender<ChainCommand>,
+    removed: Receiver<Box<dyn AudioEffect>>,
+    latency: Arc<AtomicUsize>,
+    next_id: u32,
+}
+
+pub fn effect_chain(volume: Arc<GainControl>) -> (EffectChain, EffectChainHandle) {
+    let (sender, receiver) = channel();
+    let (removed_sender, removed) = channel();
+    let latency = Arc::new(AtomicUsize::new(0));
+    let chain = EffectChain {
+        slots: Vec::with_capacity(SLOT_CAPACITY),
+        volume: Gain::new(volume),
+        commands: receiver,
+        removed: removed_sender,
+        latency: latency.clone(),
+    };
+    let handle = EffectChainHandle {
+        commands: sender,
+        removed,
+        latency,
+        next_id: 0,
+    };
+    (chain, handle)
+}
+
+impl EffectChainHandle {
+    fn send(&self, command: ChainCommand) {
+        if self.commands.send(command).is_err() {
+            warn!("Effect chain disconnected");
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, effect: Box<dyn AudioEffect>) -> EffectId {
+        let id = EffectId(self.next_id);
+        self.next_id += 1;
+        self.send(ChainCommand::Insert(index, id, effect));
+        id
+    }
+
+    pub fn remove(&self, id: EffectId) {
+        self.send(ChainCommand::Remove(id));
+    }
+
+    pub fn set_enabled(&self, id: EffectId, enabled: bool) {
+        self.send(ChainCommand::SetEnabled(id, enabled));
+    }
+
+    pub fn move_to(&self, id: EffectId, index: usize) {
+        self.send(ChainCommand::Move(id, index));
+    }
+
+    /// Total latency of all enabled effects in frames
+    pub fn latency(&self) -> usize {
+        self.latency.load(Acquire)
+    }
+
+    /// Frees the effects the chain removed, called regularly from the gui thread
+    pub fn drop_removed(&self) {
+        while self.removed.try_recv().is_ok() {}
+    }
+}
+
+impl EffectChain {
+    fn position(&self, id: EffectId) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.id == id)
+    }
+
+    fn handle_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                // Growing the slots would allocate, the gui never gets here
+                ChainCommand::Insert(_, _, effect) if self.slots.len() == SLOT_CAPACITY => {
+                    let _ = self.removed.send(effect);
+                }
+                ChainCommand::Insert(index, id, effect) => {
+                    let index = index.min(self.slots.len());
+                    self.slots.insert(
+                        index,
+                        Slot {
+                            id,
+                            effect,
+                            enabled: false,
+                            removed: false,
+                            mix: 0.0,
+                        },
+                    );
+                }
+                ChainCommand::Remove(id) => {
+                    if let Some(index) = self.position(id) {
+                        let slot = &mut self.slots[index];
+                        slot.enabled = false;
+                        slot.removed = true;
+                    }
+                }
+                ChainCommand::SetEnabled(id, enabled) => {
+                    if let Some(index) = self.position(id) {
+                        self.slots[index].enabled = enabled;
+                    }
+                }
+                ChainCommand::Move(id, to) => {
+                    if let Some(index) = self.position(id) {
+                        let slot = self.slots.remove(index);
+                        let to = to.min(self.slots.len());
+                        self.slots.insert(to, slot);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Processes the samples through all enabled effects, fading effects in and out over one
+    /// chunk when they are toggled
+    pub fn process(&mut self, samples: &mut [f32]) {
+        self.handle_commands();
+
+        for slot in self.slots.iter_mut() {
+            let target = if slot.enabled { 1.0 } else { 0.0 };
+            if slot.mix == 0.0 && target == 0.0 {
+                continue;
+            }
+            if slot.mix == target {
+                slot.effect.process(samples);
+                continue;
+            }
+
+            let frames = (samples.len() / 2).max(1);
+            let step = (target - slot.mix) / frames as f32;
+            let mut dry = [0.0; DRY_BLOCK];
+            for block in samples.chunks_mut(DRY_BLOCK) {
+                let dry = &mut dry[..block.len()];
+                dry.copy_from_slice(block);
+                slot.effect.process(block);
+                for (wet, dry) in block.chunks_exact_mut(2).zip(dry.chunks_exact(2)) {
+                    slot.mix += step;
+                    wet[0] = dry[0] + (wet[0] - dry[0]) * slot.mix;
+                    wet[1] = dry[1] + (wet[1] - dry[1]) * slot.mix;
+                }
+            }
+            slot.mix = target;
+            if !slot.enabled {
+                slot.effect.reset();
+            }
+        }
+        while let Some(index) = self
+            .slots
+            .iter()
+            .position(|slot| slot.removed && slot.mix == 0.0)
+        {
+            let slot = self.slots.remove(index);
+            let _ = self.removed.send(slot.effect);
+        }
+        self.volume.process(samples);
+
+        let latency = self
+            .slots
+            .iter()
+            .filter(|slot| slot.enabled)
+            .map(|slot| slot.effect.latency())
+            .sum();
+        self.latency.store(latency, Release);
+    }
+
+    pub fn reset(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.effect.reset();
+        }
+        self.volume.reset();
+    }
+}
@@ -0,0 +1,252 @@
+use std::f64::consts::PI;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{AcqRel, Acquire};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio_client::SAMPLE_RATE;
+use crate::effects::AudioEffect;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BandKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+}
+
+impl BandKind {
+    pub const ALL: [BandKind; 3] = [BandKind::LowShelf, BandKind::Peaking, BandKind::HighShelf];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BandKind::LowShelf => "Low shelf",
+            BandKind::Peaking => "Peaking",
+            BandKind::HighShelf => "High shelf",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Band {
+    pub kind: BandKind,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+impl Band {
+    pub fn new(kind: BandKind, frequency: f32, q: f32) -> Self {
+        Band {
+            kind,
+            frequency,
+            gain_db: 0.0,
+            q,
+        }
+    }
+}
+
+/// Bands an equalizer applies at most, the filters are allocated up front
+pub const MAX_BANDS: usize = 16;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EqualizerSettings {
+    pub bands: Vec<Band>,
+}
+
+impl Default for EqualizerSettings {
+    fn default() -> Self {
+        EqualizerSettings {
+            bands: vec![
+                Band::new(BandKind::LowShelf, 80.0, 0.707),
+                Band::new(BandKind::Peaking, 250.0, 1.0),
+                Band::new(BandKind::Peaking, 1000.0, 1.0),
+                Band::new(BandKind::Peaking, 4000.0, 1.0),
+                Band::new(BandKind::HighShelf, 12000.0, 0.707),
+            ],
+        }
+    }
+}
+
+impl EqualizerSettings {
+    /// Combined gain of all bands at `frequency`
+    pub fn response_db(&self, frequency: f64) -> f64 {
+        self.bands
+            .iter()
+            .map(|band| Coefficients::new(band).response_db(frequency))
+            .sum()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EqualizerPreset {
+    pub name: String,
+    pub bands: Vec<Band>,
+}
+
+/// Normalized biquad coefficients, see the RBJ audio EQ cookbook
+#[derive(Clone, Copy)]
+struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Coefficients {
+    fn new(band: &Band) -> Self {
+        let fs = SAMPLE_RATE as f64;
+        let frequency = (band.frequency as f64).max(1.0).min(fs * 0.49);
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+        let w0 = 2.0 * PI * frequency / fs;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * (band.q as f64).max(0.01));
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            BandKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+            BandKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ),
+        };
+
+        Coefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    fn response_db(&self, frequency: f64) -> f64 {
+        let w = 2.0 * PI * frequency / SAMPLE_RATE as f64;
+        let (sin1, cos1) = w.sin_cos();
+        let (sin2, cos2) = (2.0 * w).sin_cos();
+        let num_re = self.b0 + self.b1 * cos1 + self.b2 * cos2;
+        let num_im = -self.b1 * sin1 - self.b2 * sin2;
+        let den_re = 1.0 + self.a1 * cos1 + self.a2 * cos2;
+        let den_im = -self.a1 * sin1 - self.a2 * sin2;
+        let num = num_re * num_re + num_im * num_im;
+        let den = den_re * den_re + den_im * den_im;
+        10.0 * (num / den).log10()
+    }
+}
+
+/// Transposed direct form II biquad for a single channel
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let x = x as f64;
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y as f32
+    }
+}
+
+/// Settings shared between the gui and the audio thread
+pub struct EqualizerControl {
+    settings: Mutex<EqualizerSettings>,
+    revision: AtomicU64,
+}
+
+impl EqualizerControl {
+    pub fn new(settings: EqualizerSettings) -> Self {
+        EqualizerControl {
+            settings: Mutex::new(settings),
+            revision: AtomicU64::new(1),
+        }
+    }
+
+    pub fn set(&self, settings: EqualizerSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.revision.fetch_add(1, AcqRel);
+    }
+
+    fn try_settings(&self) -> Option<MutexGuard<'_, EqualizerSettings>> {
+        self.settings.try_lock().ok()
+    }
+}
+
+pub struct Equalizer {
+    control: Arc<EqualizerControl>,
+    revision: u64,
+    bands: Vec<(Coefficients, [Biquad; 2])>,
+}
+
+impl Equalizer {
+    pub fn new(control: Arc<EqualizerControl>) -> Self {
+        Equalizer {
+            control,
+            revision: 0,
+            bands: Vec::with_capacity(MAX_BANDS),
+        }
+    }
+
+    /// Picks up changed settings, never blocks the audio thread
+    fn update(&mut self) {
+        let revision = self.control.revision.load(Acquire);
+        if revision == self.revision {
+            return;
+        }
+        let settings = match self.control.try_settings() {
+            Some(settings) => settings,
+            None => return,
+        };
+        // Keep the filter state of existing bands to avoid clicks
+        self.bands.truncate(settings.bands.len());
+        for (i, band) in settings.bands.iter().take(MAX_BANDS).enumerate() {
+            let coefficients = Coefficients::new(band);
+            match self.bands.get_mut(i) {
+                Some(existing) => existing.0 = coefficients,
+                None => self.bands.push((coefficients, Default::default())),
+            }
+        }
+        self.revision = revision;
+    }
+}
+
+impl AudioEffect for Equalizer {
+    fn process(&mut self, samples: &mut [f32]) {
+        self.update();
+        for frame in samples.chunks_exact_mut(2) {
+            for (coefficients, filters) in self.bands.iter_mut() {
+                frame[0] = filters[0].process(coefficients, frame[0]);
+                frame[1] = filters[1].process(coefficients, frame[1]);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for (_, filters) in self.bands.iter_mut() {
+            *filters = Default::default();
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+
+use audiopus::coder::Decoder;
+
+use crate::audio_client::{create_decoder, decode_frame, PlayingInfo, SAMPLE_RATE, TIME_BASE};
+use crate::audio_socket::StreamStartMessage;
+use crate::audio_stream::AudioSource;
+use crate::gui::PlayerState;
+use crate::ogg_opus::OggOpusReader;
+
+const NO_SEEK: u64 = u64::MAX;
+/// Audio decoded and dropped before the seek target, 80 ms
+const PRE_ROLL: u64 = SAMPLE_RATE * 80 / 1000;
+
+/// Shared between the gui and a `FileSource`
+pub struct FileControl {
+    seek: AtomicU64,
+    position: AtomicU64,
+    finished: AtomicBool,
+    duration: u64,
+    /// Cover art next to the file
+    cover: Option<PathBuf>,
+}
+
+impl FileControl {
+    /// Requests a seek to `sample`, applied before the next frame is read
+    pub fn seek(&self, sample: u64) {
+        self.seek.store(sample.min(self.duration), Release);
+    }
+
+    fn take_seek(&self) -> Option<u64> {
+        match self.seek.swap(NO_SEEK, AcqRel) {
+            NO_SEEK => None,
+            sample => Some(sample),
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position.load(Acquire)
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Acquire)
+    }
+
+    pub fn cover(&self) -> Option<&Path> {
+        self.cover.as_deref()
+    }
+}
+
+/// Image with the same name as the file or a `cover`, `folder` or `front` image in its directory
+fn find_cover(path: &Path) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    let dir = match path.parent()? {
+        dir if dir.as_os_str().is_empty() => Path::new("."),
+        dir => dir,
+    };
+    // Lowercase file stems of the images in the directory
+    let mut images = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let extension = path.extension()?.to_string_lossy().to_lowercase();
+            if !matches!(extension.as_str(), "png" | "jpg" | "jpeg") {
+                return None;
+            }
+            let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+            Some((stem, path))
+        })
+        .collect::<Vec<_>>();
+    images.sort();
+    [stem.as_str(), "cover", "folder", "front"]
+        .iter()
+        .find_map(|name| images.iter().find(|(stem, _)| stem == name))
+        .map(|(_, path)| path.clone())
+}
+
+/// Plays an Ogg Opus file, e.g. a recording made by the client
+pub struct FileSource {
+    reader: OggOpusReader<BufReader<File>>,
+    decoder: Decoder,
+    control: Arc<FileControl>,
+    context: Arc<PlayerState>,
+    position: u64,
+    /// Samples per channel to drop before output starts
+    skip: u64,
+}
+
+impl FileSource {
+    pub fn open(path: &Path, context: Arc<PlayerState>) -> io::Result<(Self, Arc<FileControl>)> {
+        let reader = OggOpusReader::new(BufReader::new(File::open(path)?))?;
+        let control = Arc::new(FileControl {
+            seek: AtomicU64::new(NO_SEEK),
+            position: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+            duration: reader.duration(),
+            cover: find_cover(path),
+        });
+
+        let name = match reader.title() {
+            Some(title) => title.to_owned(),
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        context.start_resource();
+        *context.state() = PlayingInfo {
+            item: Some(StreamStartMessage {
+                offset_samples: 0,
+                start_timestamp_us: 0,
+                end_timestamp_us: Some(reader.duration() * TIME_BASE / SAMPLE_RATE),
+                duration_us: None,
+                name,
+                metadata: Default::default(),
+            }),
+            buffering: false,
+        };
+        context.set_timestamp(0);
+
+        let source = FileSource {
+            skip: reader.pre_skip(),
+            reader,
+            decoder: create_decoder(),
+            control: control.clone(),
+            context,
+            position: 0,
+        };
+        Ok((source, control))
+    }
+
+    /// Seeks to `sample`. Decoding starts on a page boundary before it, at least `PRE_ROLL`
+    /// earlier so the decoder has converged (RFC 7845), and the audio up to `sample` is dropped
+    fn seek(&mut self, sample: u64) {
+        let pre_skip = self.reader.pre_skip();
+        let start = match self.reader.seek(sample.saturating_sub(PRE_ROLL)) {
+            Ok(start) => start,
+            Err(err) => {
+                warn!("Failed to seek: {}", err);
+                return;
+            }
+        };
+        self.decoder = create_decoder();
+        let target = sample + pre_skip;
+        self.skip = target.saturating_sub(start);
+        self.set_position(start.max(target) - pre_skip);
+        self.control.finished.store(false, Release);
+    }
+
+    fn set_position(&mut self, position: u64) {
+        self.position = position;
+        self.context.set_timestamp(position);
+        self.control.position.store(position, Release);
+    }
+
+    fn finish(&mut self) {
+        self.control.finished.store(true, Release);
+    }
+}
+
+impl Iterator for FileSource {
+    type Item = Vec<f32>;
+
+    fn next(&mut self) -> Option<Vec<f32>> {
+        if let Some(sample) = self.control.take_seek() {
+            self.seek(sample);
+        }
+        while !self.control.is_finished() {
+            let data = match self.reader.next_packet() {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    info!("End of file");
+                    self.finish();
+                    break;
+                }
+                Err(err) => {
+                    warn!("Failed to read file: {}", err);
+                    self.finish();
+                    break;
+                }
+            };
+            let mut samples = match decode_frame(&mut self.decoder, data.as_slice()) {
+                Ok(samples) => samples,
+                Err(err) => {
+                    warn!("Failed to decode packet: {}", err);
+                    self.context.statistics().add_decode_error();
+                    continue;
+                }
+            };
+            let frames = (samples.len() / 2) as u64;
+            if self.skip > 0 {
+                let skipped = self.skip.min(frames);
+                self.skip -= skipped;
+                samples.drain(..skipped as usize * 2);
+                if samples.is_empty() {
+                    continue;
+                }
+            }
+            self.set_position(self.position + (samples.len() / 2) as u64);
+            return Some(samples);
+        }
+        None
+    }
+}
+
+impl AudioSource for FileSource {
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.position)
+    }
+}
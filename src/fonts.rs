@@ -0,0 +1,150 @@
+//! Font atlas built from the bundled fonts
+
+use imgui::{Context, FontConfig, FontGlyphRanges, FontSource};
+use serde::{Deserialize, Serialize};
+
+use crate::settings;
+
+const ROBOTO: &[u8] = include_bytes!("../resources/Roboto-Regular.ttf");
+const MPLUS: &[u8] = include_bytes!("../resources/mplus-1p-regular.ttf");
+const DOKDO: &[u8] = include_bytes!("../resources/Dokdo-Regular.ttf");
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UiFont {
+    /// The font built into imgui
+    ProggyClean,
+    Roboto,
+    MPlus,
+    Dokdo,
+}
+
+impl UiFont {
+    pub const ALL: [UiFont; 4] = [
+        UiFont::ProggyClean,
+        UiFont::Roboto,
+        UiFont::MPlus,
+        UiFont::Dokdo,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UiFont::ProggyClean => "ProggyClean",
+            UiFont::Roboto => "Roboto",
+            UiFont::MPlus => "M+ 1p",
+            UiFont::Dokdo => "Dokdo",
+        }
+    }
+}
+
+/// Supported font sizes in logical pixels
+pub const MIN_SIZE: f32 = 8.0;
+pub const MAX_SIZE: f32 = 32.0;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FontSettings {
+    pub font: UiFont,
+    /// Size in logical pixels
+    pub size: f32,
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        FontSettings {
+            font: UiFont::ProggyClean,
+            size: 13.0,
+        }
+    }
+}
+
+impl FontSettings {
+    /// Size within the supported range, imgui aborts on sizes of zero or less
+    pub fn clamped_size(&self) -> f32 {
+        if self.size.is_finite() {
+            self.size.clamp(MIN_SIZE, MAX_SIZE)
+        } else {
+            FontSettings::default().size
+        }
+    }
+}
+
+/// Largest size the UI font is rasterized at, larger text is scaled up
+const MAX_RASTER_SIZE: f32 = 64.0;
+/// Largest size the fallback fonts are rasterized at. The Japanese, Chinese and Korean ranges
+/// hold thousands of glyphs, larger sizes exceed texture size limits and rebuild slowly, so
+/// these glyphs are drawn smaller than the UI font at large sizes
+const MAX_FALLBACK_SIZE: f32 = 20.0;
+
+fn ttf(
+    data: &'static [u8],
+    size_pixels: f32,
+    glyph_ranges: FontGlyphRanges,
+) -> FontSource<'static> {
+    FontSource::TtfData {
+        data,
+        size_pixels,
+        config: Some(FontConfig {
+            rasterizer_multiply: 1.75,
+            glyph_ranges,
+            ..FontConfig::default()
+        }),
+    }
+}
+
+/// A fallback with many glyphs, without horizontal oversampling to keep the atlas small
+fn fallback_ttf(
+    data: &'static [u8],
+    size_pixels: f32,
+    glyph_ranges: FontGlyphRanges,
+) -> FontSource<'static> {
+    FontSource::TtfData {
+        data,
+        size_pixels: size_pixels.min(MAX_FALLBACK_SIZE),
+        config: Some(FontConfig {
+            rasterizer_multiply: 1.75,
+            oversample_h: 1,
+            glyph_ranges,
+            ..FontConfig::default()
+        }),
+    }
+}
+
+/// Rebuilds the font atlas with the selected UI font, merged with the other bundled fonts for
+/// Latin, Japanese, Chinese and Korean glyphs missing from it. The atlas is rasterized at the
+/// physical size, so text stays sharp on high DPI displays, up to a bounded size. Returns the
+/// font size in pixels
+pub fn load(imgui: &mut Context, settings: &FontSettings, zoom: f32, hidpi_factor: f64) -> f32 {
+    let zoom = settings::clamp_zoom(zoom);
+    let size = (settings.clamped_size() as f64 * zoom as f64 * hidpi_factor) as f32;
+    let raster_size = size.min(MAX_RASTER_SIZE);
+    let primary = match settings.font {
+        UiFont::ProggyClean => FontSource::DefaultFontData {
+            config: Some(FontConfig {
+                size_pixels: raster_size,
+                ..FontConfig::default()
+            }),
+        },
+        UiFont::Roboto => ttf(ROBOTO, raster_size, FontGlyphRanges::default()),
+        // Their Japanese and Korean glyphs come from the fallbacks
+        UiFont::MPlus => ttf(MPLUS, raster_size, FontGlyphRanges::default()),
+        UiFont::Dokdo => ttf(DOKDO, raster_size, FontGlyphRanges::default()),
+    };
+    // Glyphs already in the atlas are kept, so the fallbacks only fill in what's missing
+    let sources = [
+        primary,
+        ttf(ROBOTO, raster_size, FontGlyphRanges::default()),
+        fallback_ttf(MPLUS, raster_size, FontGlyphRanges::japanese()),
+        fallback_ttf(
+            MPLUS,
+            raster_size,
+            FontGlyphRanges::chinese_simplified_common(),
+        ),
+        fallback_ttf(DOKDO, raster_size, FontGlyphRanges::korean()),
+    ];
+    let mut fonts = imgui.fonts();
+    fonts.clear();
+    fonts.add_font(&sources);
+    drop(fonts);
+    imgui.io_mut().font_global_scale = (size as f64 / raster_size as f64 / hidpi_factor) as f32;
+    size
+}
@@ -1,11 +1,21 @@
-use gfx::Device;
+use gfx::{Device, Factory};
+use glutin::dpi::LogicalPosition;
 use glutin::{Event, WindowEvent};
-use imgui::{Context, FontConfig, FontGlyphRanges, FontSource, Ui};
+use image::GenericImageView;
+use imgui::{Context, Style, TextureId, Ui};
 use imgui_gfx_renderer::{Renderer, Shaders};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Release;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
+use crate::fonts::{self, FontSettings};
+use crate::settings::{self, MiniPlayerSettings, Settings};
+use crate::theme::Theme;
+
 type ColorFormat = gfx::format::Rgba8;
 
 pub struct System {
@@ -14,78 +24,241 @@ pub struct System {
     pub platform: WinitPlatform,
     pub render_sys: RenderSystem,
     pub font_size: f32,
+    /// Whether the window has keyboard focus, updated by the main loop
+    focused: Arc<AtomicBool>,
+    appearance: Appearance,
+}
+
+/// What the fonts and style sizes are built from, kept to rebuild them when the DPI changes
+struct Appearance {
+    fonts: FontSettings,
+    /// UI scale on top of the DPI scale factor
+    zoom: f32,
+    /// Style sizes before zooming
+    base_style: Style,
 }
 
-pub fn init(title: &str) -> System {
+impl Appearance {
+    /// Rebuilds the font atlas and scales the style for the current DPI and zoom
+    fn rebuild(
+        &self,
+        imgui: &mut Context,
+        platform: &WinitPlatform,
+        render_sys: &mut RenderSystem,
+    ) {
+        let style = imgui.style_mut();
+        let colors = style.colors;
+        *style = self.base_style;
+        style.colors = colors;
+        style.scale_all_sizes(self.zoom);
+        fonts::load(imgui, &self.fonts, self.zoom, platform.hidpi_factor());
+        let res = render_sys
+            .renderer
+            .reload_font_texture(imgui, &mut render_sys.factory);
+        if let Err(err) = res {
+            warn!("Failed to reload fonts: {:?}", err);
+        }
+    }
+}
+
+/// Size and decorations of the native window
+#[derive(Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    Normal,
+    /// Small borderless window for the mini player
+    Mini {
+        always_on_top: bool,
+    },
+}
+
+impl From<&MiniPlayerSettings> for WindowMode {
+    fn from(settings: &MiniPlayerSettings) -> Self {
+        if settings.enabled {
+            WindowMode::Mini {
+                always_on_top: settings.always_on_top,
+            }
+        } else {
+            WindowMode::Normal
+        }
+    }
+}
+
+impl WindowMode {
+    fn size(self) -> glutin::dpi::LogicalSize {
+        match self {
+            WindowMode::Normal => glutin::dpi::LogicalSize::new(1024f64, 768f64),
+            WindowMode::Mini { .. } => glutin::dpi::LogicalSize::new(320f64, 110f64),
+        }
+    }
+
+    fn always_on_top(self) -> bool {
+        match self {
+            WindowMode::Normal => false,
+            WindowMode::Mini { always_on_top } => always_on_top,
+        }
+    }
+
+    fn apply(self, window: &glutin::Window) {
+        window.set_decorations(self == WindowMode::Normal);
+        window.set_always_on_top(self.always_on_top());
+        window.set_inner_size(self.size());
+    }
+}
+
+/// Changes to the imgui context requested by the gui, applied between frames
+#[derive(Default)]
+pub struct Requests {
+    pub theme: Option<Theme>,
+    pub fonts: Option<FontSettings>,
+    pub zoom: Option<f32>,
+    pub window_mode: Option<WindowMode>,
+    /// Moves the native window with the cursor, set in every frame of the drag
+    pub drag_window: bool,
+}
+
+/// Moves the native window so the point grabbed at the start of a drag stays under the cursor
+#[derive(Default)]
+struct WindowDrag {
+    /// Cursor position relative to the window
+    cursor: Option<LogicalPosition>,
+    /// Cursor position relative to the window when the drag started
+    grab: Option<LogicalPosition>,
+}
+
+impl WindowDrag {
+    fn update(&mut self, window: &glutin::Window, dragging: bool) {
+        if !dragging {
+            self.grab = None;
+            return;
+        }
+        let cursor = match self.cursor {
+            Some(cursor) => cursor,
+            None => return,
+        };
+        let grab = *self.grab.get_or_insert(cursor);
+        let position = match window.get_position() {
+            Some(position) => position,
+            None => return,
+        };
+        // The cursor is at `position + cursor` on the screen
+        let target = LogicalPosition::new(
+            position.x + cursor.x - grab.x,
+            position.y + cursor.y - grab.y,
+        );
+        if target != position {
+            window.set_position(target);
+            // Until the next cursor event, the cursor is where it was grabbed
+            self.cursor = Some(grab);
+        }
+    }
+}
+
+/// `focused` is updated with the keyboard focus of the window
+pub fn init(title: &str, settings: &Settings, focused: Arc<AtomicBool>) -> System {
     let events_loop = glutin::EventsLoop::new();
+    let window_mode = WindowMode::from(&settings.mini_player);
     let builder = glutin::WindowBuilder::new()
         .with_title(title.to_owned())
-        .with_dimensions(glutin::dpi::LogicalSize::new(1024f64, 768f64));
+        .with_dimensions(window_mode.size())
+        .with_decorations(window_mode == WindowMode::Normal)
+        .with_always_on_top(window_mode.always_on_top());
+    let font_settings = &settings.font;
+    let zoom = settings.zoom;
 
     let mut imgui = Context::create();
-    imgui.set_ini_filename(None);
+    let layout_path = settings::layout_path();
+    if let Some(dir) = layout_path.as_ref().and_then(|path| path.parent()) {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            warn!("Failed to create {}: {}", dir.display(), err);
+        }
+    }
+    imgui.set_ini_filename(layout_path);
 
     let mut platform = WinitPlatform::init(&mut imgui);
 
-    let hidpi_factor = platform.hidpi_factor();
-    let font_size = (13.0 * hidpi_factor) as f32;
-    imgui.fonts().add_font(&[
-        FontSource::DefaultFontData {
-            config: Some(FontConfig {
-                size_pixels: font_size,
-                ..FontConfig::default()
-            }),
-        },
-        FontSource::TtfData {
-            data: include_bytes!("../resources/Roboto-Regular.ttf"),
-            size_pixels: font_size,
-            config: Some(FontConfig {
-                rasterizer_multiply: 1.75,
-                glyph_ranges: FontGlyphRanges::japanese(),
-                ..FontConfig::default()
-            }),
-        },
-    ]);
-
-    imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
+    let font_size = fonts::load(&mut imgui, font_settings, zoom, platform.hidpi_factor());
 
     let render_sys = RenderSystem::init(&mut imgui, builder, &events_loop);
     platform.attach_window(imgui.io_mut(), render_sys.window(), HiDpiMode::Rounded);
-    System {
+    let appearance = Appearance {
+        fonts: *font_settings,
+        zoom,
+        base_style: *imgui.style(),
+    };
+    imgui.style_mut().scale_all_sizes(zoom);
+    let mut system = System {
         events_loop,
         imgui,
         platform,
         render_sys,
         font_size,
+        focused,
+        appearance,
+    };
+    // The scale factor is only known once the window is attached
+    if system.platform.hidpi_factor() != 1.0 {
+        system
+            .appearance
+            .rebuild(&mut system.imgui, &system.platform, &mut system.render_sys);
     }
+    system
 }
 
 impl System {
-    pub async fn main_loop<F: FnMut(&mut bool, &mut Ui)>(self, mut run_ui: F) {
+    pub fn apply(&mut self, requests: Requests) {
+        apply_requests(
+            &mut self.imgui,
+            &self.platform,
+            &mut self.render_sys,
+            &mut self.appearance,
+            requests,
+        );
+    }
+
+    pub async fn main_loop<F: FnMut(&mut bool, &mut Ui, &mut Requests, &mut Textures)>(
+        self,
+        mut run_ui: F,
+    ) {
         let System {
             mut events_loop,
             mut imgui,
             mut platform,
             mut render_sys,
+            mut appearance,
+            focused,
             ..
         } = self;
         let mut encoder: gfx::Encoder<_, _> = render_sys.factory.create_command_buffer().into();
 
         let mut last_frame = Instant::now();
         let mut run = true;
+        let mut drag = WindowDrag::default();
 
         while run {
+            let mut hidpi_changed = false;
             events_loop.poll_events(|event| {
                 platform.handle_event(imgui.io_mut(), render_sys.window(), &event);
 
                 if let Event::WindowEvent { event, .. } = event {
                     match event {
                         WindowEvent::Resized(size) => render_sys.update_views(size),
+                        WindowEvent::HiDpiFactorChanged(_) => {
+                            // The physical size changes even if the logical size doesn't
+                            if let Some(size) = render_sys.window().get_inner_size() {
+                                render_sys.update_views(size);
+                            }
+                            hidpi_changed = true;
+                        }
+                        WindowEvent::Focused(state) => focused.store(state, Release),
+                        WindowEvent::CursorMoved { position, .. } => drag.cursor = Some(position),
                         WindowEvent::CloseRequested => run = false,
                         _ => (),
                     }
                 }
             });
+            if hidpi_changed {
+                appearance.rebuild(&mut imgui, &platform, &mut render_sys);
+            }
 
             let io = imgui.io_mut();
             platform
@@ -95,7 +268,12 @@ impl System {
             io.update_delta_time(now - last_frame);
             last_frame = now;
             let mut ui = imgui.frame();
-            run_ui(&mut run, &mut ui);
+            let mut requests = Requests::default();
+            render_sys.texture_cache.next_frame();
+            let mut textures = Textures {
+                render_sys: &mut render_sys,
+            };
+            run_ui(&mut run, &mut ui, &mut requests, &mut textures);
 
             if let Some(main_color) = render_sys.main_color.as_ref() {
                 encoder.clear(main_color, [0.01, 0.01, 0.01, 1.0]);
@@ -111,11 +289,235 @@ impl System {
             encoder.flush(&mut render_sys.device);
             render_sys.swap_buffers();
             render_sys.device.cleanup();
+            drag.update(render_sys.window(), requests.drag_window);
+            apply_requests(
+                &mut imgui,
+                &platform,
+                &mut render_sys,
+                &mut appearance,
+                requests,
+            );
             tokio::time::delay_for(Duration::from_millis(25)).await;
         }
     }
 }
 
+/// Where an image for the gui comes from, also the key of its texture in the cache
+#[derive(Clone)]
+pub enum ImageSource {
+    /// Encoded PNG or JPEG data, identified by its allocation
+    Data(Arc<[u8]>),
+    File(PathBuf),
+}
+
+impl ImageSource {
+    fn is_same(&self, other: &ImageSource) -> bool {
+        match (self, other) {
+            (ImageSource::Data(a), ImageSource::Data(b)) => Arc::ptr_eq(a, b),
+            (ImageSource::File(a), ImageSource::File(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Decodes the image on the gui thread, so oversized ones are refused before decoding
+    fn decode(&self) -> Result<image::DynamicImage, String> {
+        let file;
+        let data = match self {
+            ImageSource::Data(data) => &data[..],
+            ImageSource::File(path) => {
+                let len = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+                if len > MAX_IMAGE_BYTES as u64 {
+                    return Err(format!("{} bytes is too large", len));
+                }
+                file = std::fs::read(path).map_err(|e| e.to_string())?;
+                &file[..]
+            }
+        };
+        if data.len() > MAX_IMAGE_BYTES {
+            return Err(format!("{} bytes is too large", data.len()));
+        }
+        let reader = || {
+            image::io::Reader::new(std::io::Cursor::new(data))
+                .with_guessed_format()
+                .map_err(|e| e.to_string())
+        };
+        let (width, height) = reader()?.into_dimensions().map_err(|e| e.to_string())?;
+        if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+            return Err(format!("{}x{} is too large", width, height));
+        }
+        reader()?.decode().map_err(|e| e.to_string())
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            ImageSource::Data(data) => format!("{} bytes of image data", data.len()),
+            ImageSource::File(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// Images kept as textures, the least recently used one is dropped for a new one
+const TEXTURE_CACHE_SIZE: usize = 8;
+/// Larger images are scaled down before uploading
+const MAX_TEXTURE_SIZE: u32 = 512;
+/// Larger encoded images aren't decoded
+const MAX_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+/// Images with a larger width or height aren't decoded
+const MAX_IMAGE_DIMENSION: u32 = 4096;
+
+struct CachedTexture {
+    source: ImageSource,
+    /// `None` if the image failed to load, so it isn't retried every frame
+    texture: Option<TextureId>,
+    last_used: u64,
+}
+
+/// Textures by image source with least recently used eviction
+pub struct TextureCache {
+    entries: Vec<CachedTexture>,
+    capacity: usize,
+    frame: u64,
+}
+
+impl TextureCache {
+    pub fn new(capacity: usize) -> Self {
+        TextureCache {
+            entries: Vec::new(),
+            capacity,
+            frame: 0,
+        }
+    }
+
+    /// Textures used in the current frame are never evicted, they are still drawn
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Texture of `source`, calling `load` if it isn't cached. Also returns the texture evicted
+    /// to make room, which has to be removed from the renderer
+    pub fn get_or_load<F: FnOnce() -> Option<TextureId>>(
+        &mut self,
+        source: &ImageSource,
+        load: F,
+    ) -> (Option<TextureId>, Option<TextureId>) {
+        let frame = self.frame;
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.source.is_same(source)) {
+            entry.last_used = frame;
+            return (entry.texture, None);
+        }
+        let mut evicted = None;
+        if self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.last_used < frame)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(index, _)| index);
+            if let Some(index) = oldest {
+                evicted = self.entries.swap_remove(index).texture;
+            }
+        }
+        let texture = load();
+        self.entries.push(CachedTexture {
+            source: source.clone(),
+            texture,
+            last_used: frame,
+        });
+        (texture, evicted)
+    }
+}
+
+/// Images the gui can draw with `imgui::Image`, uploaded during the frame
+pub struct Textures<'a> {
+    render_sys: &'a mut RenderSystem,
+}
+
+impl Textures<'_> {
+    /// Texture of a PNG or JPEG image, loaded on first use. Errors are logged and give `None`
+    pub fn get(&mut self, source: &ImageSource) -> Option<TextureId> {
+        let RenderSystem {
+            renderer,
+            factory,
+            texture_cache,
+            ..
+        } = &mut *self.render_sys;
+        let (texture, evicted) =
+            texture_cache.get_or_load(source, || load_texture(renderer, factory, source));
+        if let Some(evicted) = evicted {
+            renderer.textures().remove(evicted);
+        }
+        texture
+    }
+}
+
+fn load_texture(
+    renderer: &mut Renderer<ColorFormat, types::Resources>,
+    factory: &mut types::Factory,
+    source: &ImageSource,
+) -> Option<TextureId> {
+    let image = match source.decode() {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("Failed to decode {}: {}", source.describe(), err);
+            return None;
+        }
+    };
+    let image = if image.width() > MAX_TEXTURE_SIZE || image.height() > MAX_TEXTURE_SIZE {
+        image.thumbnail(MAX_TEXTURE_SIZE, MAX_TEXTURE_SIZE)
+    } else {
+        image
+    };
+    let image = image.to_rgba8();
+    let kind = gfx::texture::Kind::D2(
+        image.width() as u16,
+        image.height() as u16,
+        gfx::texture::AaMode::Single,
+    );
+    let res = factory.create_texture_immutable_u8::<gfx::format::Srgba8>(
+        kind,
+        gfx::texture::Mipmap::Provided,
+        &[image.as_raw()],
+    );
+    let view = match res {
+        Ok((_, view)) => view,
+        Err(err) => {
+            warn!("Failed to upload {}: {}", source.describe(), err);
+            return None;
+        }
+    };
+    let sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
+        gfx::texture::FilterMethod::Bilinear,
+        gfx::texture::WrapMode::Clamp,
+    ));
+    Some(renderer.textures().insert((view, sampler)))
+}
+
+fn apply_requests(
+    imgui: &mut Context,
+    platform: &WinitPlatform,
+    render_sys: &mut RenderSystem,
+    appearance: &mut Appearance,
+    requests: Requests,
+) {
+    if let Some(theme) = requests.theme {
+        theme.apply(imgui.style_mut());
+    }
+    if let Some(window_mode) = requests.window_mode {
+        window_mode.apply(render_sys.window());
+    }
+    if requests.fonts.is_none() && requests.zoom.is_none() {
+        return;
+    }
+    if let Some(fonts) = requests.fonts {
+        appearance.fonts = fonts;
+    }
+    if let Some(zoom) = requests.zoom {
+        appearance.zoom = zoom;
+    }
+    appearance.rebuild(imgui, platform, render_sys);
+}
+
 mod types {
     pub type Device = gfx_device_gl::Device;
     pub type Factory = gfx_device_gl::Factory;
@@ -129,6 +531,7 @@ pub struct RenderSystem {
     pub factory: types::Factory,
     pub main_color: Option<gfx::handle::RenderTargetView<types::Resources, ColorFormat>>,
     pub main_depth: gfx::handle::DepthStencilView<types::Resources, gfx::format::DepthStencil>,
+    pub texture_cache: TextureCache,
 }
 
 impl RenderSystem {
@@ -137,21 +540,7 @@ impl RenderSystem {
         builder: glutin::WindowBuilder,
         events_loop: &glutin::EventsLoop,
     ) -> RenderSystem {
-        {
-            // Fix incorrect colors with sRGB framebuffer
-            fn imgui_gamma_to_linear(col: [f32; 4]) -> [f32; 4] {
-                let x = col[0].powf(2.2);
-                let y = col[1].powf(2.2);
-                let z = col[2].powf(2.2);
-                let w = 1.0 - (1.0 - col[3]).powf(2.2);
-                [x, y, z, w]
-            }
-
-            let style = imgui.style_mut();
-            for col in 0..style.colors.len() {
-                style.colors[col] = imgui_gamma_to_linear(style.colors[col]);
-            }
-        }
+        Theme::default().apply(imgui.style_mut());
 
         let context = glutin::ContextBuilder::new().with_vsync(true);
         let (windowed_context, device, mut factory, main_color, main_depth) =
@@ -186,6 +575,7 @@ impl RenderSystem {
             factory,
             main_color: Some(main_color),
             main_depth,
+            texture_cache: TextureCache::new(TEXTURE_CACHE_SIZE),
         }
     }
     pub fn window(&self) -> &glutin::Window {
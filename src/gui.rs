@@ -1,38 +1,105 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::ffi::CString;
 use std::iter::FromIterator;
 use std::ops::Deref;
-use std::sync::atomic::Ordering::Acquire;
+use std::path::PathBuf;
 use std::sync::atomic::Ordering::Release;
+use std::sync::atomic::Ordering::{AcqRel, Acquire};
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
 
-use futures_util::core_reexport::sync::atomic::{AtomicU64, AtomicUsize};
-use imgui::{Condition, ImStr, PlotLines, ProgressBar, Window};
+use imgui::{Condition, ImStr, MenuItem, PlotLines, ProgressBar, Slider, Window};
 use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
 
-use crate::audio_client::{PlayingInfo, SAMPLE_RATE, TIME_BASE};
+use crate::audio_client::{
+    PlayingInfo, ResumeMode, Transition, SAMPLES_PER_FRAME, SAMPLE_RATE, TIME_BASE,
+};
 use crate::audio_socket::{AudioMessage, AudioSocket};
+use crate::control::ControlCommand;
+use crate::effects::{linear_to_db, EffectChainHandle, EffectControls, GainControl};
+use crate::file_source::FileControl;
+use crate::gfx_system::{Requests, Textures, WindowMode};
+use crate::gui::diagnostics::DiagnosticsWindow;
+use crate::gui::effects::EffectsWindow;
+use crate::gui::equalizer::EqualizerWindow;
+use crate::gui::file_player::FilePlayer;
+use crate::gui::history::HistoryWindow;
+use crate::gui::settings::SettingsWindow;
+use crate::gui::shortcuts::{Action, Shortcuts};
+use crate::gui::theme::ThemeWindow;
+use crate::notifications::Notifier;
+use crate::now_playing::{self, ResourceTime};
+use crate::playback_clock::PlaybackClock;
+use crate::recorder::Recorder;
+use crate::session::{SessionReplay, SharedSessionWriter};
+use crate::settings::{clamp_zoom, Settings, WindowSettings};
+use crate::statistics::Statistics;
 use crate::token::*;
 use crate::{audio_socket, format, ADDRESS};
 
+mod diagnostics;
+mod effects;
+mod equalizer;
+mod file_player;
+pub mod history;
+mod metadata;
+mod mini_player;
+mod settings;
+pub mod shortcuts;
+mod theme;
+
+const PLAYING: u8 = 0;
+const PAUSED: u8 = 1;
+/// Playing again, the audio thread still has to drop the backlog
+const RESUMING_LIVE: u8 = 2;
+
+const NO_SEEK: u64 = u64::MAX;
+
+fn seconds_to_frames(seconds: u32) -> usize {
+    let frames = seconds as u64 * SAMPLE_RATE / SAMPLES_PER_FRAME;
+    frames.max(1) as usize
+}
+
 pub struct PlayerState {
     state: Mutex<PlayingInfo>,
+    /// Counts the resources started, identifies the current one
+    resource_id: AtomicU64,
     timestamp: AtomicU64,
     buffer: AtomicUsize,
     target_buffer: AtomicUsize,
+    pause: AtomicU8,
+    /// Frames kept while paused, older ones are dropped
+    max_pause_buffer: AtomicUsize,
+    /// Played frames kept for rewinding
+    max_history: AtomicUsize,
+    /// Timestamp to seek to, `NO_SEEK` if there is no pending seek
+    seek: AtomicU64,
+    transition: Mutex<Transition>,
+    statistics: Arc<Statistics>,
+    clock: Arc<PlaybackClock>,
 }
 
 impl PlayerState {
-    pub fn new() -> Self {
+    pub fn new(transition: Transition) -> Self {
         PlayerState {
             state: Mutex::new(PlayingInfo {
                 item: None,
                 buffering: true,
             }),
+            resource_id: Default::default(),
             timestamp: Default::default(),
             buffer: Default::default(),
             target_buffer: AtomicUsize::new(50),
+            pause: AtomicU8::new(PLAYING),
+            max_pause_buffer: AtomicUsize::new(3000),
+            max_history: AtomicUsize::new(15000),
+            seek: AtomicU64::new(NO_SEEK),
+            transition: Mutex::new(transition),
+            statistics: Default::default(),
+            clock: Default::default(),
         }
     }
 
@@ -40,10 +107,39 @@ impl PlayerState {
         self.state.lock().unwrap()
     }
 
+    pub fn transition(&self) -> MutexGuard<'_, Transition> {
+        self.transition.lock().unwrap()
+    }
+
+    /// The transition settings, `None` while the gui holds them. For the audio thread
+    pub fn try_transition(&self) -> Option<Transition> {
+        self.transition
+            .try_lock()
+            .ok()
+            .map(|transition| *transition)
+    }
+
+    pub fn statistics(&self) -> &Arc<Statistics> {
+        &self.statistics
+    }
+
+    pub fn clock(&self) -> &Arc<PlaybackClock> {
+        &self.clock
+    }
+
+    /// Timestamp of the last decoded frame, ahead of what is heard by the output latency
     pub fn timestamp(&self) -> u64 {
         self.timestamp.load(Acquire)
     }
 
+    /// Position in the current resource that is heard now, interpolated between output
+    /// callbacks. Falls back to the decoded timestamp without output
+    pub fn position(&self) -> u64 {
+        self.clock
+            .position(Instant::now())
+            .unwrap_or_else(|| self.timestamp())
+    }
+
     pub fn buffer(&self) -> usize {
         self.buffer.load(Acquire)
     }
@@ -52,17 +148,101 @@ impl PlayerState {
         self.target_buffer.load(Acquire)
     }
 
+    pub fn resource_id(&self) -> u64 {
+        self.resource_id.load(Acquire)
+    }
+
+    /// Gives the resource about to be played a new id
+    pub fn start_resource(&self) {
+        self.resource_id.fetch_add(1, AcqRel);
+    }
+
     pub fn set_timestamp(&self, timestamp: u64) {
         self.timestamp.store(timestamp, Release);
     }
 
-    pub fn set_buffer(&self, buffer: usize) {
+    /// Sets the buffered messages, `replay` of them were rewound and aren't part of the
+    /// jitter buffer statistics
+    pub fn set_buffer(&self, buffer: usize, replay: usize) {
         self.buffer.store(buffer, Release);
+        self.statistics.update_buffer(buffer - replay);
     }
 
     pub fn set_target_buffer(&self, target_buffer: usize) {
         self.target_buffer.store(target_buffer, Release);
     }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.load(Acquire) == PAUSED
+    }
+
+    /// Outputs silence while the audio keeps being received
+    pub fn pause(&self) {
+        self.pause.store(PAUSED, Release);
+    }
+
+    pub fn resume(&self, mode: ResumeMode) {
+        let state = match mode {
+            ResumeMode::Live => RESUMING_LIVE,
+            ResumeMode::TimeShift => PLAYING,
+        };
+        let _ = self.pause.compare_exchange(PAUSED, state, AcqRel, Acquire);
+    }
+
+    /// Clears the pause without any resume handling, e.g. after disconnecting
+    pub fn clear_pause(&self) {
+        self.pause.store(PLAYING, Release);
+    }
+
+    /// Returns true once after a live resume
+    pub fn take_live_resume(&self) -> bool {
+        self.pause
+            .compare_exchange(RESUMING_LIVE, PLAYING, AcqRel, Acquire)
+            .is_ok()
+    }
+
+    pub fn max_pause_buffer(&self) -> usize {
+        self.max_pause_buffer.load(Acquire)
+    }
+
+    pub fn set_max_pause_buffer(&self, frames: usize) {
+        self.max_pause_buffer.store(frames, Release);
+    }
+
+    /// Sets the pause buffer from a duration in seconds
+    pub fn set_max_pause_seconds(&self, seconds: u32) {
+        self.set_max_pause_buffer(seconds_to_frames(seconds));
+    }
+
+    pub fn max_history(&self) -> usize {
+        self.max_history.load(Acquire)
+    }
+
+    pub fn set_max_history_seconds(&self, seconds: u32) {
+        self.max_history.store(seconds_to_frames(seconds), Release);
+    }
+
+    /// Seeks to a timestamp in the current resource, limited to the history and the buffer
+    pub fn seek(&self, timestamp: u64) {
+        self.seek.store(timestamp, Release);
+    }
+
+    pub fn take_seek(&self) -> Option<u64> {
+        match self.seek.swap(NO_SEEK, AcqRel) {
+            NO_SEEK => None,
+            timestamp => Some(timestamp),
+        }
+    }
+
+    /// Drops the backlog after pausing or rewinding and continues with the live stream
+    pub fn go_live(&self) {
+        self.pause.store(RESUMING_LIVE, Release);
+    }
+
+    /// Frames buffered beyond the target buffer, i.e. how far playback lags behind live
+    pub fn behind_live(&self) -> usize {
+        self.buffer().saturating_sub(self.target_buffer())
+    }
 }
 
 pub type PlayerToken = Token<CancelableToken<CompletableToken<ValueToken<()>>>>;
@@ -74,19 +254,118 @@ pub struct Player {
     packet_output: Sender<AudioMessage>,
     handle: Option<JoinHandle<()>>,
     buffer_sizes: VecDeque<usize>,
+    /// Position in seconds while the seek slider is dragged
+    seek_target: Option<f32>,
+    /// Records the traffic of all connections
+    session: Option<SharedSessionWriter>,
+    /// Replays this session file instead of connecting to the server
+    replay: Option<PathBuf>,
 }
 
 impl Player {
     pub fn create_player(&self) -> JoinHandle<()> {
+        if let Some(path) = self.replay.as_ref() {
+            let replay = SessionReplay::new(
+                path.clone(),
+                self.token.clone(),
+                self.socket_state.clone(),
+                self.packet_output.clone(),
+                self.player_state.statistics().clone(),
+            );
+            return tokio::spawn(async move { replay.run().await });
+        }
         let socket = AudioSocket::new(
             ADDRESS.into(),
             self.token.clone(),
             self.socket_state.clone(),
             self.packet_output.clone(),
+            self.player_state.statistics().clone(),
+            self.session.clone(),
         );
         tokio::spawn(async move { socket.run().await })
     }
 
+    /// Starts connecting unless a connection is already running
+    pub fn connect(&mut self) {
+        if self.handle.is_none() {
+            self.handle = Some(self.create_player());
+        }
+    }
+
+    /// Cancels the running connection and clears the current resource
+    pub fn disconnect(&self) {
+        if self.handle.is_some() && !self.token.is_canceled() {
+            self.token.cancel();
+            self.player_state.clear_pause();
+            self.player_state.clock().reset();
+            let mut info = self.player_state.state();
+            info.item = None;
+            info.buffering = true;
+        }
+    }
+
+    /// Pauses playback if connected, the connection is kept alive
+    pub fn pause(&self) {
+        if self.handle.is_some() && !self.token.is_canceled() {
+            self.player_state.pause();
+        }
+    }
+
+    /// Seek slider over the current resource, open ended resources end at the live position
+    fn build_seek(
+        ui: &imgui::Ui,
+        player_state: &PlayerState,
+        seek_target: &mut Option<f32>,
+        time: ResourceTime,
+    ) {
+        let position_us = time.position_us(player_state.position());
+        let behind_us =
+            now_playing::samples_to_us(player_state.behind_live() as u64 * SAMPLES_PER_FRAME);
+        let end_us = time.duration_us.unwrap_or(position_us + behind_us);
+        let mut position_s = seek_target.unwrap_or(position_us as f32 / TIME_BASE as f32);
+        if Slider::new(im_str!("##seek"))
+            .range(0.0..=(end_us as f32 / TIME_BASE as f32).max(0.001))
+            .display_format(im_str!(""))
+            .build(ui, &mut position_s)
+        {
+            *seek_target = Some(position_s);
+        }
+        if ui.is_item_deactivated_after_edit() {
+            if let Some(target) = seek_target.take() {
+                player_state.seek(time.timestamp_at((target * TIME_BASE as f32) as u64));
+            }
+        }
+    }
+
+    fn build_pause(&self, ui: &imgui::Ui) {
+        if !self.player_state.is_paused() {
+            if ui.button(im_str!("Pause"), [0.0, 0.0]) {
+                self.player_state.pause();
+            }
+            // Rewound or resumed time-shifted by more than a second
+            if self.player_state.behind_live() as u64 * SAMPLES_PER_FRAME >= SAMPLE_RATE {
+                ui.same_line(0.0);
+                if ui.button(im_str!("Go live"), [0.0, 0.0]) {
+                    self.player_state.go_live();
+                }
+            }
+            return;
+        }
+        if ui.button(im_str!("Resume"), [0.0, 0.0]) {
+            self.player_state.resume(ResumeMode::TimeShift);
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Go live"), [0.0, 0.0]) {
+            self.player_state.resume(ResumeMode::Live);
+        }
+        ui.same_line(0.0);
+        let behind = self.player_state.buffer() as u64 * SAMPLES_PER_FRAME / SAMPLE_RATE;
+        ui.text(format!(
+            "Paused, {} behind",
+            format::format_timestamp(behind as i64)
+        ));
+    }
+
     pub fn update(&mut self) {
         let handle = self.handle.take();
         self.handle = match handle {
@@ -94,6 +373,7 @@ impl Player {
             Some(handle) => {
                 if self.token.is_completed() {
                     self.token.reset();
+                    self.player_state.clear_pause();
                     *self.socket_state.lock().unwrap() = audio_socket::State::None;
                     None
                 } else {
@@ -103,12 +383,16 @@ impl Player {
         };
     }
 
-    pub fn build(&mut self, ui: &imgui::Ui) {
+    pub fn build(&mut self, ui: &imgui::Ui, textures: &mut Textures) {
         self.update();
         match self.socket_state.lock().unwrap().deref() {
             audio_socket::State::None => {
                 ui.text(im_str!("Not connected"));
-                if ui.button(im_str!("Connect"), [0.0, 0.0]) {
+                let label = match self.replay {
+                    Some(_) => im_str!("Replay session"),
+                    None => im_str!("Connect"),
+                };
+                if ui.button(label, [0.0, 0.0]) {
                     self.handle = Some(self.create_player());
                 }
             }
@@ -116,11 +400,15 @@ impl Player {
                 ui.text(im_str!("Connecting..."));
             }
             audio_socket::State::Connected => {
-                let mut info = self.player_state.state.lock().unwrap();
+                // Copied out so loading the cover doesn't block the audio thread
+                let (item, buffering) = {
+                    let info = self.player_state.state();
+                    (info.item.clone(), info.buffering)
+                };
                 if self.token.is_canceled() {
                     ui.text(im_str!("Disconnecting..."));
                 } else {
-                    let timestamp_us = self.player_state.timestamp() * TIME_BASE / SAMPLE_RATE;
+                    let position = self.player_state.position();
 
                     struct Current<'a> {
                         name: &'a str,
@@ -128,25 +416,16 @@ impl Player {
                         duration_s: Option<i64>,
                     }
 
-                    let (current, progress) = match info.item.as_ref() {
-                        Some(item) => {
-                            let timestamp_us = item.start_timestamp_us + timestamp_us;
-                            let (duration_s, progress) = match item.end_timestamp_us.clone() {
-                                Some(end_timestamp_us) => (
-                                    Some((end_timestamp_us / TIME_BASE) as i64),
-                                    timestamp_us as f32 / end_timestamp_us as f32,
-                                ),
-                                None => (None, 0.0),
-                            };
-                            let current = Current {
-                                name: item.name.as_str(),
-                                timestamp: (timestamp_us / TIME_BASE) as i64,
-                                duration_s,
-                            };
-                            (Some(current), progress)
+                    let current = item.as_ref().map(|item| {
+                        let time = ResourceTime::new(item);
+                        Current {
+                            name: item.name.as_str(),
+                            timestamp: (time.position_us(position) / TIME_BASE) as i64,
+                            duration_s: time
+                                .duration_us
+                                .map(|duration| (duration / TIME_BASE) as i64),
                         }
-                        None => (None, 0.0),
-                    };
+                    });
 
                     ui.text("Title:");
                     ui.same_line(0.0);
@@ -159,6 +438,9 @@ impl Player {
                     } else {
                         ui.text(im_str!("-"));
                     }
+                    if let Some(item) = item.as_ref() {
+                        metadata::build(ui, textures, &item.metadata);
+                    }
 
                     ui.text(im_str!("Timestamp:"));
                     ui.same_line(0.0);
@@ -175,21 +457,36 @@ impl Player {
                         ui.text(im_str!("--:--"));
                     }
 
-                    if info.buffering {
+                    if buffering {
                         ui.same_line_with_spacing(0.0, 20.0);
                         ui.text(im_str!("Buffering"));
+                    } else {
+                        let behind_s = self.player_state.behind_live() as u64 * SAMPLES_PER_FRAME
+                            / SAMPLE_RATE;
+                        ui.same_line_with_spacing(0.0, 20.0);
+                        if behind_s == 0 {
+                            ui.text(im_str!("LIVE"));
+                        } else {
+                            ui.text(format!("-{}", format::format_timestamp(behind_s as i64)));
+                        }
                     }
 
                     ui.spacing();
 
-                    ProgressBar::new(progress)
-                        .overlay_text(im_str!(""))
-                        .build(ui);
+                    match item.as_ref() {
+                        Some(item) => Self::build_seek(
+                            ui,
+                            &self.player_state,
+                            &mut self.seek_target,
+                            ResourceTime::new(item),
+                        ),
+                        None => ProgressBar::new(0.0).overlay_text(im_str!("")).build(ui),
+                    }
                     if ui.button(im_str!("Disconnect"), [0.0, 0.0]) {
-                        self.token.cancel();
-                        info.item = None;
-                        info.buffering = true;
+                        self.disconnect();
                     }
+                    ui.same_line(0.0);
+                    self.build_pause(ui);
 
                     {
                         let new_buffer = self.player_state.buffer();
@@ -224,12 +521,94 @@ impl Player {
     }
 }
 
+/// Volume change of the volume shortcuts
+const VOLUME_STEP: f32 = 0.05;
+
+const ZOOM_STEP: f32 = 0.1;
+
+/// Windows whose placement is reset the next time they are drawn, closed windows keep it
+/// until they are opened again
+#[derive(Default)]
+struct LayoutReset {
+    pending: HashSet<&'static str>,
+}
+
+impl LayoutReset {
+    const WINDOWS: [&'static str; 7] = [
+        "Player",
+        "Equalizer",
+        "Effects",
+        "Settings",
+        "Diagnostics",
+        "History",
+        "Theme",
+    ];
+
+    fn reset_all(&mut self) {
+        self.pending.extend(Self::WINDOWS.iter());
+    }
+
+    /// Placement condition of `window` in this frame
+    fn condition(&mut self, window: &'static str, opened: bool) -> Condition {
+        if opened && self.pending.remove(window) {
+            Condition::Always
+        } else {
+            Condition::FirstUseEver
+        }
+    }
+}
+
 pub struct GuiState {
     player: Player,
+    /// Replaces the player controls when playing a file
+    file_player: Option<FilePlayer>,
+    effects: EffectsWindow,
+    equalizer: EqualizerWindow,
+    settings_window: SettingsWindow,
+    diagnostics: DiagnosticsWindow,
+    history: HistoryWindow,
+    shortcuts: Shortcuts,
+    theme: ThemeWindow,
+    player_opened: bool,
+    layout_reset: LayoutReset,
+    zoom_changed: bool,
+    window_mode_changed: bool,
+    /// The mini player window is being moved with the mouse
+    mini_player_dragging: bool,
+    volume: Arc<GainControl>,
+    /// Volume to restore when unmuting
+    muted_volume: Option<f32>,
+    /// Commands from the control API
+    commands: Option<Receiver<ControlCommand>>,
+    recorder: Recorder,
+    notifier: Option<Notifier>,
+    settings: Settings,
 }
 
 impl GuiState {
-    pub fn new(packet_output: Sender<AudioMessage>, player_state: Arc<PlayerState>) -> Self {
+    pub fn new(
+        packet_output: Sender<AudioMessage>,
+        player_state: Arc<PlayerState>,
+        effect_chain: EffectChainHandle,
+        effect_controls: EffectControls,
+        recorder: Recorder,
+        settings: Settings,
+    ) -> Self {
+        let statistics = player_state.statistics().clone();
+        let volume = effect_controls.volume.clone();
+        let windows = &settings.windows;
+        let mut equalizer = EqualizerWindow::new(effect_controls.equalizer.clone());
+        equalizer.opened = windows.equalizer;
+        let mut effects = EffectsWindow::new(effect_chain, effect_controls, &settings);
+        effects.opened = windows.effects;
+        let mut settings_window = SettingsWindow::new(&settings);
+        settings_window.opened = windows.settings;
+        let mut diagnostics = DiagnosticsWindow::new(statistics);
+        diagnostics.opened = windows.diagnostics;
+        let mut history = HistoryWindow::new(player_state.clone());
+        history.opened = windows.history;
+        let mut theme = ThemeWindow::new(&settings);
+        theme.opened = windows.theme;
         GuiState {
             player: Player {
                 token: PlayerToken::default(),
@@ -238,13 +617,342 @@ impl GuiState {
                 handle: None,
                 socket_state: Arc::new(Mutex::new(audio_socket::State::None)),
                 buffer_sizes: VecDeque::from_iter(std::iter::repeat(0).take(10 * 1000 / 20)),
+                seek_target: None,
+                session: None,
+                replay: None,
             },
+            file_player: None,
+            equalizer,
+            effects,
+            settings_window,
+            diagnostics,
+            history,
+            shortcuts: Shortcuts::new(&settings.shortcuts),
+            theme,
+            player_opened: settings.windows.player,
+            layout_reset: LayoutReset::default(),
+            zoom_changed: false,
+            window_mode_changed: false,
+            mini_player_dragging: false,
+            volume,
+            muted_volume: None,
+            commands: None,
+            recorder,
+            notifier: None,
+            settings,
+        }
+    }
+
+    pub fn set_file(&mut self, control: Arc<FileControl>) {
+        self.file_player = Some(FilePlayer::new(control, self.player.player_state.clone()));
+    }
+
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    pub fn socket_state(&self) -> Arc<Mutex<audio_socket::State>> {
+        self.player.socket_state.clone()
+    }
+
+    /// Sets the receiver for commands from the control API and MPRIS
+    pub fn set_control(&mut self, commands: Receiver<ControlCommand>) {
+        self.commands = Some(commands);
+    }
+
+    fn handle_commands(&mut self) {
+        let commands = match self.commands.as_ref() {
+            Some(commands) => commands.try_iter().collect::<Vec<_>>(),
+            None => return,
+        };
+        for command in commands {
+            match command {
+                // There is no connection while playing a file
+                ControlCommand::Connect
+                | ControlCommand::Disconnect
+                | ControlCommand::Pause
+                | ControlCommand::Resume
+                    if self.file_player.is_some() => {}
+                ControlCommand::Connect => self.player.connect(),
+                ControlCommand::Disconnect => self.player.disconnect(),
+                ControlCommand::Pause => self.player.pause(),
+                ControlCommand::Resume => self
+                    .player
+                    .player_state
+                    .resume(self.settings.pause.resume_mode),
+                ControlCommand::SetVolume(volume) => self.set_volume(volume),
+            }
+        }
+    }
+
+    /// Sets the volume, which also ends muting
+    fn set_volume(&mut self, volume: f32) {
+        self.muted_volume = None;
+        self.settings.volume = volume.clamp(0.0, 1.0);
+        self.volume.set_gain_db(linear_to_db(self.settings.volume));
+    }
+
+    fn connected(&self) -> bool {
+        matches!(
+            *self.player.socket_state.lock().unwrap(),
+            audio_socket::State::Connected
+        )
+    }
+
+    /// Connects if disconnected, otherwise disconnects
+    fn toggle_connection(&mut self) {
+        if self.file_player.is_some() {
+            return;
+        }
+        if self.connected() {
+            self.player.disconnect();
+        } else {
+            self.player.connect();
+        }
+    }
+
+    /// Pauses or resumes while connected
+    fn toggle_pause(&mut self) {
+        if self.file_player.is_some() || !self.connected() {
+            return;
+        }
+        if self.player.player_state.is_paused() {
+            let mode = self.settings.pause.resume_mode;
+            self.player.player_state.resume(mode);
+        } else {
+            self.player.pause();
+        }
+    }
+
+    fn handle_shortcuts(&mut self, ui: &imgui::Ui) {
+        for action in self.shortcuts.pressed(ui) {
+            match action {
+                Action::Connect => self.toggle_connection(),
+                Action::Pause => self.toggle_pause(),
+                Action::VolumeUp => self.set_volume(self.settings.volume + VOLUME_STEP),
+                Action::VolumeDown => self.set_volume(self.settings.volume - VOLUME_STEP),
+                Action::Mute => match self.muted_volume {
+                    Some(volume) => self.set_volume(volume),
+                    None => {
+                        let volume = self.settings.volume;
+                        self.set_volume(0.0);
+                        self.muted_volume = Some(volume);
+                    }
+                },
+                Action::Diagnostics => self.diagnostics.opened = !self.diagnostics.opened,
+                Action::Help => self.shortcuts.help_opened = !self.shortcuts.help_opened,
+            }
+        }
+    }
+
+    /// Volume slider, moving it ends muting
+    fn build_volume(
+        volume: &GainControl,
+        settings: &mut Settings,
+        muted_volume: &mut Option<f32>,
+        ui: &imgui::Ui,
+    ) {
+        let mut percent = settings.volume * 100.0;
+        if Slider::new(im_str!("Volume"))
+            .range(0.0..=100.0)
+            .display_format(im_str!("%.0f %%"))
+            .build(ui, &mut percent)
+        {
+            *muted_volume = None;
+            settings.volume = percent / 100.0;
+            volume.set_gain_db(linear_to_db(settings.volume));
+        }
+    }
+
+    pub fn set_session_recording(&mut self, session: SharedSessionWriter) {
+        self.player.session = Some(session);
+    }
+
+    pub fn set_replay(&mut self, path: PathBuf) {
+        self.player.replay = Some(path);
+    }
+
+    /// Saves the settings and finishes running recordings
+    pub fn exit(mut self) {
+        self.settings.windows = WindowSettings {
+            player: self.player_opened,
+            equalizer: self.equalizer.opened,
+            effects: self.effects.opened,
+            settings: self.settings_window.opened,
+            diagnostics: self.diagnostics.opened,
+            history: self.history.opened,
+            theme: self.theme.opened,
+        };
+        self.settings.save();
+        self.recorder.shutdown();
+        if let Some(notifier) = self.notifier {
+            notifier.shutdown();
+        }
+    }
+
+    fn build_recorder(recorder: &Recorder, settings: &Settings, ui: &imgui::Ui) {
+        if recorder.is_recording() {
+            if ui.button(im_str!("Stop recording"), [0.0, 0.0]) {
+                recorder.stop();
+            }
+            if let Some(file) = recorder.current_file() {
+                ui.same_line(0.0);
+                if let Some(name) = file.file_name() {
+                    ui.text(name.to_string_lossy());
+                }
+            }
+        } else if ui.button(im_str!("Record"), [0.0, 0.0]) {
+            recorder.start(
+                settings.recording.directory.clone(),
+                settings.recording.format,
+            );
+        }
+    }
+
+    fn build_menu(&mut self, ui: &imgui::Ui) {
+        ui.main_menu_bar(|| {
+            ui.menu(im_str!("Windows"), true, || {
+                MenuItem::new(im_str!("Player")).build_with_ref(ui, &mut self.player_opened);
+                MenuItem::new(im_str!("Equalizer")).build_with_ref(ui, &mut self.equalizer.opened);
+                MenuItem::new(im_str!("Effects")).build_with_ref(ui, &mut self.effects.opened);
+                MenuItem::new(im_str!("Settings"))
+                    .build_with_ref(ui, &mut self.settings_window.opened);
+                MenuItem::new(im_str!("Diagnostics"))
+                    .build_with_ref(ui, &mut self.diagnostics.opened);
+                MenuItem::new(im_str!("History")).build_with_ref(ui, &mut self.history.opened);
+                MenuItem::new(im_str!("Theme")).build_with_ref(ui, &mut self.theme.opened);
+                ui.separator();
+                MenuItem::new(im_str!("Shortcuts"))
+                    .build_with_ref(ui, &mut self.shortcuts.help_opened);
+            });
+            ui.menu(im_str!("View"), true, || {
+                ui.text(format!("Zoom: {:.0} %", self.settings.zoom * 100.0));
+                if MenuItem::new(im_str!("Zoom in")).build(ui) {
+                    self.set_zoom(self.settings.zoom + ZOOM_STEP);
+                }
+                if MenuItem::new(im_str!("Zoom out")).build(ui) {
+                    self.set_zoom(self.settings.zoom - ZOOM_STEP);
+                }
+                if MenuItem::new(im_str!("Reset zoom")).build(ui) {
+                    self.set_zoom(1.0);
+                }
+                ui.separator();
+                if MenuItem::new(im_str!("Mini player")).build(ui) {
+                    self.settings.mini_player.enabled = true;
+                    self.window_mode_changed = true;
+                }
+            });
+            ui.menu(im_str!("Layout"), true, || {
+                if MenuItem::new(im_str!("Reset layout")).build(ui) {
+                    self.layout_reset.reset_all();
+                    self.player_opened = true;
+                }
+            });
+        });
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    fn set_zoom(&mut self, zoom: f32) {
+        self.settings.zoom = clamp_zoom(zoom);
+        self.zoom_changed = true;
+    }
+
+    /// Collects the changes to apply to the imgui context
+    pub fn take_requests(&mut self, requests: &mut Requests) {
+        requests.theme = self.theme.take_theme();
+        requests.fonts = self.settings_window.take_fonts();
+        if std::mem::take(&mut self.zoom_changed) {
+            requests.zoom = Some(self.settings.zoom);
+        }
+        if std::mem::take(&mut self.window_mode_changed) {
+            requests.window_mode = Some(WindowMode::from(&self.settings.mini_player));
         }
     }
 
-    pub fn build(&mut self, ui: &mut imgui::Ui) {
-        Window::new(im_str!("Player"))
-            .size([400.0, 150.0], Condition::FirstUseEver)
-            .build(ui, || self.player.build(ui));
+    pub fn build(&mut self, ui: &mut imgui::Ui, requests: &mut Requests, textures: &mut Textures) {
+        self.handle_commands();
+        self.handle_shortcuts(ui);
+        self.history.update();
+        if self.settings.mini_player.enabled {
+            self.window_mode_changed |= mini_player::build(
+                ui,
+                &self.player.player_state,
+                &self.volume,
+                &mut self.settings,
+                &mut self.muted_volume,
+                &mut self.mini_player_dragging,
+                requests,
+            );
+            self.shortcuts.build_help(ui);
+            self.take_requests(requests);
+            return;
+        }
+        self.build_menu(ui);
+        let layout_reset = &mut self.layout_reset;
+        let mut layout = |window, opened| layout_reset.condition(window, opened);
+        let player_layout = layout("Player", self.player_opened);
+        let equalizer_layout = layout("Equalizer", self.equalizer.opened);
+        let effects_layout = layout("Effects", self.effects.opened);
+        let diagnostics_layout = layout("Diagnostics", self.diagnostics.opened);
+        let history_layout = layout("History", self.history.opened);
+        let theme_layout = layout("Theme", self.theme.opened);
+        let settings_layout = layout("Settings", self.settings_window.opened);
+        let player = &mut self.player;
+        let file_player = &mut self.file_player;
+        let equalizer = &mut self.equalizer;
+        let effects = &mut self.effects;
+        let settings_window = &mut self.settings_window;
+        let diagnostics = &mut self.diagnostics;
+        let recorder = &self.recorder;
+        let volume = &self.volume;
+        let settings = &mut self.settings;
+        let muted_volume = &mut self.muted_volume;
+        if self.player_opened {
+            Window::new(im_str!("Player"))
+                .position([10.0, 30.0], player_layout)
+                .size([400.0, 200.0], player_layout)
+                .opened(&mut self.player_opened)
+                .build(ui, || {
+                    match file_player.as_mut() {
+                        Some(file_player) => file_player.build(ui, textures),
+                        None => player.build(ui, textures),
+                    }
+                    ui.separator();
+                    Self::build_volume(volume, settings, muted_volume, ui);
+                    ui.checkbox(im_str!("Equalizer"), &mut equalizer.opened);
+                    ui.same_line(0.0);
+                    ui.checkbox(im_str!("Effects"), &mut effects.opened);
+                    ui.same_line(0.0);
+                    ui.checkbox(im_str!("Settings"), &mut settings_window.opened);
+                    ui.same_line(0.0);
+                    ui.checkbox(im_str!("Diagnostics"), &mut diagnostics.opened);
+                    // Only the live stream is passed to the recorder
+                    if file_player.is_none() {
+                        Self::build_recorder(recorder, settings, ui);
+                    }
+                });
+        }
+        self.equalizer
+            .build(ui, equalizer_layout, &mut self.settings, &self.effects);
+        self.effects.build(ui, effects_layout, &mut self.settings);
+        self.diagnostics.build(ui, diagnostics_layout);
+        self.history.build(ui, history_layout);
+        self.shortcuts.build_help(ui);
+        self.theme.build(ui, theme_layout, &mut self.settings);
+        self.settings_window.build(
+            ui,
+            settings_layout,
+            &mut self.settings,
+            &self.player.player_state,
+        );
+        if let Some(notifications) = self.settings_window.take_notifications() {
+            if let Some(notifier) = self.notifier.as_ref() {
+                notifier.configure(notifications);
+            }
+        }
+        self.take_requests(requests);
     }
 }
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use imgui::{Condition, Window};
+
+use crate::audio_client::{SAMPLES_PER_FRAME, SAMPLE_RATE};
+use crate::format;
+use crate::statistics::{Counters, Statistics};
+
+/// Interval over which the rates are measured
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct DiagnosticsWindow {
+    statistics: Arc<Statistics>,
+    pub opened: bool,
+    /// Counters when Reset was pressed, the shared ones keep counting
+    baseline: Counters,
+    /// Time and counters at the start of the current rate interval
+    last_update: Instant,
+    last_packets: u64,
+    last_bytes: u64,
+    packet_rate: f32,
+    byte_rate: f32,
+    /// Bitrate of the Opus stream in bit/s, independent of the arrival rate
+    bitrate: f32,
+}
+
+impl DiagnosticsWindow {
+    pub fn new(statistics: Arc<Statistics>) -> Self {
+        DiagnosticsWindow {
+            statistics,
+            opened: false,
+            baseline: Counters::default(),
+            last_update: Instant::now(),
+            last_packets: 0,
+            last_bytes: 0,
+            packet_rate: 0.0,
+            byte_rate: 0.0,
+            bitrate: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.statistics.reset_measurements();
+        self.baseline = self.statistics.counters();
+        self.last_update = Instant::now();
+        self.last_packets = self.baseline.packets;
+        self.last_bytes = self.baseline.bytes;
+        self.packet_rate = 0.0;
+        self.byte_rate = 0.0;
+        self.bitrate = 0.0;
+    }
+
+    fn update_rates(&mut self) {
+        let elapsed = self.last_update.elapsed();
+        if elapsed < RATE_INTERVAL {
+            return;
+        }
+        let packets = self.statistics.packets();
+        let bytes = self.statistics.bytes();
+        let new_packets = packets.saturating_sub(self.last_packets);
+        let new_bytes = bytes.saturating_sub(self.last_bytes);
+        let seconds = elapsed.as_secs_f32();
+        self.packet_rate = new_packets as f32 / seconds;
+        self.byte_rate = new_bytes as f32 / seconds;
+        if new_packets > 0 {
+            let audio_seconds = (new_packets * SAMPLES_PER_FRAME) as f32 / SAMPLE_RATE as f32;
+            self.bitrate = new_bytes as f32 * 8.0 / audio_seconds;
+        }
+        self.last_update = Instant::now();
+        self.last_packets = packets;
+        self.last_bytes = bytes;
+    }
+
+    fn build_contents(&mut self, ui: &imgui::Ui) {
+        self.update_rates();
+        let statistics = &self.statistics;
+        let counters = statistics.counters().since(&self.baseline);
+        let frame_ms = (SAMPLES_PER_FRAME * 1000 / SAMPLE_RATE) as usize;
+
+        ui.text(format!("Underruns: {}", counters.underruns));
+        ui.text(format!("Decode errors: {}", counters.decode_errors));
+        ui.text(format!("Reconnects: {}", counters.reconnects));
+        ui.separator();
+        ui.text(format!(
+            "Packets: {} ({:.1}/s)",
+            counters.packets, self.packet_rate
+        ));
+        ui.text(format!(
+            "Received: {:.1} KiB ({:.1} KiB/s)",
+            counters.bytes as f32 / 1024.0,
+            self.byte_rate / 1024.0
+        ));
+        ui.text(format!("Bitrate: {:.1} kbit/s", self.bitrate / 1000.0));
+        ui.text(format!(
+            "Jitter: {:.1} ms",
+            statistics.jitter().as_secs_f32() * 1000.0
+        ));
+        ui.separator();
+        match statistics.buffer_range() {
+            Some((min, max)) => ui.text(format!(
+                "Buffer: {} - {} ms",
+                min * frame_ms,
+                max * frame_ms
+            )),
+            None => ui.text(im_str!("Buffer: -")),
+        }
+        ui.text(format!(
+            "Time buffering: {}",
+            format::format_timestamp(statistics.buffering_time().as_secs() as i64)
+        ));
+        ui.separator();
+        if ui.button(im_str!("Reset"), [0.0, 0.0]) {
+            self.reset();
+        }
+    }
+
+    pub fn build(&mut self, ui: &imgui::Ui, layout: Condition) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Diagnostics"))
+            .position([10.0, 240.0], layout)
+            .size([300.0, 250.0], layout)
+            .opened(&mut opened)
+            .build(ui, || self.build_contents(ui));
+        self.opened = opened;
+    }
+}
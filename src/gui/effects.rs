@@ -0,0 +1,175 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use imgui::{ComboBox, Condition, ImString, Slider, Window};
+
+use crate::audio_client::SAMPLE_RATE;
+use crate::effects::{
+    EffectChainHandle, EffectConfig, EffectControls, EffectId, EffectKind, GainControl, MAX_EFFECTS,
+};
+use crate::settings::Settings;
+
+/// An effect in the chain as seen by the gui
+struct EffectEntry {
+    id: EffectId,
+    /// Own control of a Gain effect
+    gain: Option<Arc<GainControl>>,
+}
+
+pub struct EffectsWindow {
+    chain: EffectChainHandle,
+    controls: EffectControls,
+    /// Effects in the chain, in the same order as `Settings::effects`
+    entries: Vec<EffectEntry>,
+    pub opened: bool,
+    add_kind: usize,
+}
+
+impl EffectsWindow {
+    pub fn new(
+        mut chain: EffectChainHandle,
+        controls: EffectControls,
+        settings: &Settings,
+    ) -> Self {
+        let entries = settings
+            .effects
+            .iter()
+            .enumerate()
+            .map(|(i, config)| Self::insert(&mut chain, &controls, i, config))
+            .collect();
+        EffectsWindow {
+            chain,
+            controls,
+            entries,
+            opened: false,
+            add_kind: 0,
+        }
+    }
+
+    fn insert(
+        chain: &mut EffectChainHandle,
+        controls: &EffectControls,
+        index: usize,
+        config: &EffectConfig,
+    ) -> EffectEntry {
+        let new = controls.create(config);
+        let id = chain.insert(index, new.effect);
+        chain.set_enabled(id, config.enabled);
+        EffectEntry { id, gain: new.gain }
+    }
+
+    fn set_enabled(&self, settings: &mut Settings, index: usize, enabled: bool) {
+        settings.effects[index].enabled = enabled;
+        self.chain.set_enabled(self.entries[index].id, enabled);
+    }
+
+    /// Draws a checkbox toggling the first effect of `kind`, if there is one
+    pub fn build_enabled(&self, ui: &imgui::Ui, settings: &mut Settings, kind: EffectKind) {
+        if let Some(index) = settings.effects.iter().position(|e| e.kind == kind) {
+            let mut enabled = settings.effects[index].enabled;
+            if ui.checkbox(im_str!("Enabled"), &mut enabled) {
+                self.set_enabled(settings, index, enabled);
+            }
+        }
+    }
+
+    fn move_effect(&mut self, settings: &mut Settings, from: usize, to: usize) {
+        settings.effects.swap(from, to);
+        self.entries.swap(from, to);
+        self.chain.move_to(self.entries[to].id, to);
+    }
+
+    fn build_contents(&mut self, ui: &imgui::Ui, settings: &mut Settings) {
+        let mut remove = None;
+        let mut move_up = None;
+        let count = settings.effects.len();
+        for i in 0..count {
+            let id = ui.push_id(i as i32);
+            let mut enabled = settings.effects[i].enabled;
+            if ui.checkbox(im_str!("##enabled"), &mut enabled) {
+                self.set_enabled(settings, i, enabled);
+            }
+            ui.same_line(0.0);
+            ui.text(settings.effects[i].kind.name());
+            ui.same_line(120.0);
+            if i > 0 && ui.small_button(im_str!("Up")) {
+                move_up = Some(i);
+            }
+            ui.same_line(150.0);
+            if i + 1 < count && ui.small_button(im_str!("Down")) {
+                move_up = Some(i + 1);
+            }
+            ui.same_line(195.0);
+            if ui.small_button(im_str!("x")) {
+                remove = Some(i);
+            }
+            if let Some(gain) = self.entries[i].gain.as_ref() {
+                let config = &mut settings.effects[i];
+                ui.same_line(0.0);
+                ui.set_next_item_width(150.0);
+                if Slider::new(im_str!("##gain"))
+                    .range(-24.0..=12.0)
+                    .display_format(im_str!("%.1f dB"))
+                    .build(ui, &mut config.gain_db)
+                {
+                    gain.set_gain_db(config.gain_db);
+                }
+            }
+            id.pop(ui);
+        }
+
+        if let Some(i) = move_up {
+            self.move_effect(settings, i, i - 1);
+        }
+        if let Some(i) = remove {
+            settings.effects.remove(i);
+            self.chain.remove(self.entries.remove(i).id);
+        }
+
+        ui.separator();
+        if settings.effects.len() >= MAX_EFFECTS {
+            ui.text_disabled(format!("At most {} effects", MAX_EFFECTS));
+        } else {
+            // The equalizer window edits a single equalizer
+            let has_equalizer = settings
+                .effects
+                .iter()
+                .any(|config| config.kind == EffectKind::Equalizer);
+            let kinds = EffectKind::ALL
+                .iter()
+                .copied()
+                .filter(|kind| *kind != EffectKind::Equalizer || !has_equalizer)
+                .collect::<Vec<_>>();
+            self.add_kind = self.add_kind.min(kinds.len() - 1);
+            ui.set_next_item_width(120.0);
+            ComboBox::new(im_str!("##add")).build_simple(ui, &mut self.add_kind, &kinds, &|kind| {
+                Cow::Owned(ImString::new(kind.name()))
+            });
+            ui.same_line(0.0);
+            if ui.button(im_str!("Add effect"), [0.0, 0.0]) {
+                let config = EffectConfig::new(kinds[self.add_kind]);
+                let index = settings.effects.len();
+                let entry = Self::insert(&mut self.chain, &self.controls, index, &config);
+                settings.effects.push(config);
+                self.entries.push(entry);
+            }
+        }
+
+        let latency_ms = self.chain.latency() as f32 * 1000.0 / SAMPLE_RATE as f32;
+        ui.text(format!("Latency: {:.1} ms", latency_ms));
+    }
+
+    pub fn build(&mut self, ui: &imgui::Ui, layout: Condition, settings: &mut Settings) {
+        self.chain.drop_removed();
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Effects"))
+            .position([10.0, 500.0], layout)
+            .size([400.0, 200.0], layout)
+            .opened(&mut opened)
+            .build(ui, || self.build_contents(ui, settings));
+        self.opened = opened;
+    }
+}
@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use imgui::{ComboBox, Condition, ImString, PlotLines, Slider, SliderFlags, Window};
+
+use crate::effects::EffectKind;
+use crate::equalizer::{Band, BandKind, EqualizerControl, EqualizerPreset, MAX_BANDS};
+use crate::gui::effects::EffectsWindow;
+use crate::settings::Settings;
+
+const RESPONSE_POINTS: usize = 120;
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20000.0;
+const MAX_GAIN_DB: f32 = 12.0;
+
+pub struct EqualizerWindow {
+    control: Arc<EqualizerControl>,
+    pub opened: bool,
+    selected_preset: usize,
+    preset_name: ImString,
+}
+
+impl EqualizerWindow {
+    pub fn new(control: Arc<EqualizerControl>) -> Self {
+        EqualizerWindow {
+            control,
+            opened: false,
+            selected_preset: 0,
+            preset_name: ImString::with_capacity(64),
+        }
+    }
+
+    fn response(settings: &Settings) -> Vec<f32> {
+        let ratio = (MAX_FREQUENCY / MIN_FREQUENCY) as f64;
+        (0..RESPONSE_POINTS)
+            .map(|i| {
+                let frequency =
+                    MIN_FREQUENCY as f64 * ratio.powf(i as f64 / (RESPONSE_POINTS - 1) as f64);
+                settings.equalizer.response_db(frequency) as f32
+            })
+            .collect()
+    }
+
+    /// Returns true if a band was changed
+    fn build_band(ui: &imgui::Ui, band: &mut Band) -> bool {
+        let mut changed = false;
+
+        let mut kind = BandKind::ALL
+            .iter()
+            .position(|kind| *kind == band.kind)
+            .unwrap_or(0);
+        ui.set_next_item_width(100.0);
+        if ComboBox::new(im_str!("##kind")).build_simple(ui, &mut kind, &BandKind::ALL, &|kind| {
+            Cow::Owned(ImString::new(kind.name()))
+        }) {
+            band.kind = BandKind::ALL[kind];
+            changed = true;
+        }
+
+        ui.same_line(0.0);
+        ui.set_next_item_width(120.0);
+        changed |= Slider::new(im_str!("##frequency"))
+            .range(MIN_FREQUENCY..=MAX_FREQUENCY)
+            .display_format(im_str!("%.0f Hz"))
+            .flags(SliderFlags::LOGARITHMIC)
+            .build(ui, &mut band.frequency);
+
+        ui.same_line(0.0);
+        ui.set_next_item_width(120.0);
+        changed |= Slider::new(im_str!("##gain"))
+            .range(-MAX_GAIN_DB..=MAX_GAIN_DB)
+            .display_format(im_str!("%.1f dB"))
+            .build(ui, &mut band.gain_db);
+
+        ui.same_line(0.0);
+        ui.set_next_item_width(80.0);
+        changed |= Slider::new(im_str!("##q"))
+            .range(0.1..=10.0)
+            .display_format(im_str!("Q %.2f"))
+            .flags(SliderFlags::LOGARITHMIC)
+            .build(ui, &mut band.q);
+
+        changed
+    }
+
+    fn build_presets(&mut self, ui: &imgui::Ui, settings: &mut Settings) -> bool {
+        let mut changed = false;
+
+        if !settings.equalizer_presets.is_empty() {
+            self.selected_preset = self
+                .selected_preset
+                .min(settings.equalizer_presets.len() - 1);
+            ui.set_next_item_width(200.0);
+            ComboBox::new(im_str!("##preset")).build_simple(
+                ui,
+                &mut self.selected_preset,
+                &settings.equalizer_presets,
+                &|preset: &EqualizerPreset| Cow::Owned(ImString::new(preset.name.as_str())),
+            );
+            ui.same_line(0.0);
+            if ui.button(im_str!("Load"), [0.0, 0.0]) {
+                settings.equalizer.bands = settings.equalizer_presets[self.selected_preset]
+                    .bands
+                    .clone();
+                changed = true;
+            }
+            ui.same_line(0.0);
+            if ui.button(im_str!("Delete"), [0.0, 0.0]) {
+                settings.equalizer_presets.remove(self.selected_preset);
+                settings.save();
+            }
+        }
+
+        ui.set_next_item_width(200.0);
+        ui.input_text(im_str!("##name"), &mut self.preset_name)
+            .build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Save preset"), [0.0, 0.0]) && !self.preset_name.is_empty() {
+            let preset = EqualizerPreset {
+                name: self.preset_name.to_str().to_owned(),
+                bands: settings.equalizer.bands.clone(),
+            };
+            match settings
+                .equalizer_presets
+                .iter()
+                .position(|p| p.name == preset.name)
+            {
+                Some(index) => {
+                    settings.equalizer_presets[index] = preset;
+                    self.selected_preset = index;
+                }
+                None => {
+                    settings.equalizer_presets.push(preset);
+                    self.selected_preset = settings.equalizer_presets.len() - 1;
+                }
+            }
+            self.preset_name.clear();
+            settings.save();
+        }
+
+        changed
+    }
+
+    fn build_contents(&mut self, ui: &imgui::Ui, settings: &mut Settings, effects: &EffectsWindow) {
+        effects.build_enabled(ui, settings, EffectKind::Equalizer);
+        let mut changed = false;
+
+        let response = Self::response(settings);
+        PlotLines::new(ui, im_str!("Response [dB]"), response.as_slice())
+            .graph_size([0.0, 80.0])
+            .scale_min(-MAX_GAIN_DB)
+            .scale_max(MAX_GAIN_DB)
+            .build();
+
+        ui.separator();
+
+        let mut remove = None;
+        for (i, band) in settings.equalizer.bands.iter_mut().enumerate() {
+            let id = ui.push_id(i as i32);
+            changed |= Self::build_band(ui, band);
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("x")) {
+                remove = Some(i);
+            }
+            id.pop(ui);
+        }
+        if let Some(i) = remove {
+            settings.equalizer.bands.remove(i);
+            changed = true;
+        }
+        if settings.equalizer.bands.len() >= MAX_BANDS {
+            ui.text_disabled(format!("At most {} bands", MAX_BANDS));
+        } else if ui.button(im_str!("Add band"), [0.0, 0.0]) {
+            settings
+                .equalizer
+                .bands
+                .push(Band::new(BandKind::Peaking, 1000.0, 1.0));
+            changed = true;
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Flat"), [0.0, 0.0]) {
+            for band in settings.equalizer.bands.iter_mut() {
+                band.gain_db = 0.0;
+            }
+            changed = true;
+        }
+
+        ui.separator();
+        changed |= self.build_presets(ui, settings);
+
+        if changed {
+            self.control.set(settings.equalizer.clone());
+        }
+    }
+
+    pub fn build(
+        &mut self,
+        ui: &imgui::Ui,
+        layout: Condition,
+        settings: &mut Settings,
+        effects: &EffectsWindow,
+    ) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Equalizer"))
+            .position([440.0, 440.0], layout)
+            .size([560.0, 320.0], layout)
+            .opened(&mut opened)
+            .build(ui, || self.build_contents(ui, settings, effects));
+        self.opened = opened;
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use imgui::{ImString, Image, Slider};
+
+use crate::audio_client::SAMPLE_RATE;
+use crate::file_source::FileControl;
+use crate::format;
+use crate::gfx_system::{ImageSource, Textures};
+use crate::gui::metadata::COVER_SIZE;
+use crate::gui::PlayerState;
+
+/// Controls for playing back a file instead of the live stream
+pub struct FilePlayer {
+    control: Arc<FileControl>,
+    player_state: Arc<PlayerState>,
+    /// Position in seconds while the seek slider is dragged
+    seek_target: Option<f32>,
+}
+
+impl FilePlayer {
+    pub fn new(control: Arc<FileControl>, player_state: Arc<PlayerState>) -> Self {
+        FilePlayer {
+            control,
+            player_state,
+            seek_target: None,
+        }
+    }
+
+    pub fn build(&mut self, ui: &imgui::Ui, textures: &mut Textures) {
+        let cover = self
+            .control
+            .cover()
+            .and_then(|path| textures.get(&ImageSource::File(path.to_owned())));
+        if let Some(texture) = cover {
+            Image::new(texture, [COVER_SIZE, COVER_SIZE]).build(ui);
+        }
+        ui.text("Title:");
+        ui.same_line(0.0);
+        match self.player_state.state().item.as_ref() {
+            Some(item) => ui.text_wrapped(&ImString::new(item.name.as_str())),
+            None => ui.text(im_str!("-")),
+        }
+
+        let duration_s = self.control.duration() as f32 / SAMPLE_RATE as f32;
+        let mut position_s = self
+            .seek_target
+            .unwrap_or(self.control.position() as f32 / SAMPLE_RATE as f32);
+
+        ui.text(im_str!("Timestamp:"));
+        ui.same_line(0.0);
+        ui.text(format::format_timestamp(position_s as i64));
+        ui.same_line(0.0);
+        ui.text(im_str!("-"));
+        ui.same_line(0.0);
+        ui.text(format::format_timestamp(duration_s as i64));
+        if self.control.is_finished() {
+            ui.same_line_with_spacing(0.0, 20.0);
+            ui.text(im_str!("Finished"));
+        }
+
+        if Slider::new(im_str!("##seek"))
+            .range(0.0..=duration_s.max(0.001))
+            .display_format(im_str!(""))
+            .build(ui, &mut position_s)
+        {
+            self.seek_target = Some(position_s);
+        }
+        if ui.is_item_deactivated_after_edit() {
+            if let Some(target) = self.seek_target.take() {
+                self.control.seek((target * SAMPLE_RATE as f32) as u64);
+            }
+        }
+
+        if ui.button(im_str!("Restart"), [0.0, 0.0]) {
+            self.control.seek(0);
+        }
+    }
+}
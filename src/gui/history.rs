@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+
+use imgui::{Condition, Window};
+
+use crate::audio_client::TIME_BASE;
+use crate::audio_socket::StreamStartMessage;
+use crate::format;
+use crate::gui::metadata;
+use crate::gui::PlayerState;
+use crate::now_playing::ResourceTime;
+
+/// Resources kept in the history, older ones are dropped
+const MAX_ENTRIES: usize = 100;
+
+struct Entry {
+    started: Instant,
+    item: StreamStartMessage,
+}
+
+/// Resources played since the client started, newest first
+pub struct HistoryWindow {
+    player_state: Arc<PlayerState>,
+    pub opened: bool,
+    entries: VecDeque<Entry>,
+    /// Id of the newest resource in `entries`
+    last_resource: u64,
+}
+
+impl HistoryWindow {
+    pub fn new(player_state: Arc<PlayerState>) -> Self {
+        HistoryWindow {
+            player_state,
+            opened: false,
+            entries: VecDeque::new(),
+            last_resource: 0,
+        }
+    }
+
+    /// Adds the resource started since the last frame, also while the window is closed
+    pub fn update(&mut self) {
+        let resource = self.player_state.resource_id();
+        if resource == self.last_resource {
+            return;
+        }
+        self.last_resource = resource;
+        let item = match self.player_state.state().item.clone() {
+            Some(item) => item,
+            None => return,
+        };
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(Entry {
+            started: Instant::now(),
+            item,
+        });
+    }
+
+    /// Names of the entries, newest first
+    #[cfg(test)]
+    pub fn names(&self) -> Vec<&str> {
+        self.entries
+            .iter()
+            .map(|entry| entry.item.name.as_str())
+            .collect()
+    }
+
+    fn build_contents(&self, ui: &imgui::Ui) {
+        if self.entries.is_empty() {
+            ui.text_disabled("Nothing played yet");
+        }
+        for (i, entry) in self.entries.iter().enumerate() {
+            let item = &entry.item;
+            ui.text_wrapped(&imgui::ImString::new(item.name.as_str()));
+            let mut details = Vec::new();
+            if let Some(artist) = item.metadata.artist.as_ref() {
+                details.push(artist.clone());
+            }
+            if let Some(duration_us) = ResourceTime::new(item).duration_us {
+                details.push(format::format_timestamp((duration_us / TIME_BASE) as i64));
+            }
+            details.push(format!(
+                "{} ago",
+                format::format_timestamp(entry.started.elapsed().as_secs() as i64)
+            ));
+            ui.text_disabled(details.join(" - "));
+            if let Some(url) = item.metadata.source_url.as_ref() {
+                ui.same_line(0.0);
+                if ui.small_button(&im_str!("Open##{}", i)) {
+                    metadata::open_url(url);
+                }
+            }
+            ui.separator();
+        }
+    }
+
+    pub fn build(&mut self, ui: &imgui::Ui, layout: Condition) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("History"))
+            .position([320.0, 240.0], layout)
+            .size([300.0, 250.0], layout)
+            .opened(&mut opened)
+            .build(ui, || self.build_contents(ui));
+        self.opened = opened;
+    }
+}
@@ -0,0 +1,87 @@
+use std::process::Command;
+
+use imgui::{Image, MouseButton, MouseCursor, StyleColor};
+
+use crate::audio_socket::TrackMetadata;
+use crate::gfx_system::{ImageSource, Textures};
+
+pub const COVER_SIZE: f32 = 64.0;
+
+/// Opens a web page in the default browser, other schemes are ignored
+pub fn open_url(url: &str) {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        warn!("Not opening {}, only web links are supported", url);
+        return;
+    }
+    // No shell in between, the URL comes from the server
+    #[cfg(target_os = "windows")]
+    let res = Command::new("rundll32")
+        .args(&["url.dll,FileProtocolHandler", url])
+        .spawn();
+    #[cfg(target_os = "macos")]
+    let res = Command::new("open").arg(url).spawn();
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let res = Command::new("xdg-open").arg(url).spawn();
+    if let Err(err) = res {
+        warn!("Failed to open {}: {}", url, err);
+    }
+}
+
+/// Text that opens `url` when clicked
+fn link(ui: &imgui::Ui, label: &str, url: &str) {
+    ui.text_colored(ui.style_color(StyleColor::ButtonActive), label);
+    if ui.is_item_hovered() {
+        ui.set_mouse_cursor(Some(MouseCursor::Hand));
+        ui.tooltip_text(url);
+    }
+    if ui.is_item_clicked(MouseButton::Left) {
+        open_url(url);
+    }
+}
+
+fn field(ui: &imgui::Ui, label: &str, value: Option<&String>) {
+    if let Some(value) = value {
+        ui.text(format!("{}:", label));
+        ui.same_line(0.0);
+        ui.text_wrapped(&imgui::ImString::new(value.as_str()));
+    }
+}
+
+/// Cover art next to the details the server sent about the resource
+pub fn build(ui: &imgui::Ui, textures: &mut Textures, metadata: &TrackMetadata) {
+    let cover = metadata
+        .thumbnail
+        .as_ref()
+        .and_then(|data| textures.get(&ImageSource::Data(data.clone())));
+    if let Some(texture) = cover {
+        Image::new(texture, [COVER_SIZE, COVER_SIZE]).build(ui);
+        if let Some(url) = metadata.thumbnail_url.as_ref() {
+            if ui.is_item_hovered() {
+                ui.set_mouse_cursor(Some(MouseCursor::Hand));
+            }
+            if ui.is_item_clicked(MouseButton::Left) {
+                open_url(url);
+            }
+        }
+        ui.same_line(0.0);
+    }
+    ui.group(|| {
+        field(ui, "Artist", metadata.artist.as_ref());
+        field(ui, "Album", metadata.album.as_ref());
+        field(ui, "Requested by", metadata.requester.as_ref());
+        if !metadata.tags.is_empty() {
+            ui.text_disabled(metadata.tags.join(", "));
+        }
+        if let Some(url) = metadata.source_url.as_ref() {
+            link(ui, "Source", url);
+        }
+        if cover.is_none() {
+            if let Some(url) = metadata.thumbnail_url.as_ref() {
+                if metadata.source_url.is_some() {
+                    ui.same_line(0.0);
+                }
+                link(ui, "Cover", url);
+            }
+        }
+    });
+}
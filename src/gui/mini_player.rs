@@ -0,0 +1,80 @@
+use imgui::{Condition, ImString, MouseButton, ProgressBar, Window};
+
+use crate::audio_client::TIME_BASE;
+use crate::effects::GainControl;
+use crate::format;
+use crate::gfx_system::Requests;
+use crate::gui::{GuiState, PlayerState};
+use crate::now_playing::ResourceTime;
+use crate::settings::Settings;
+
+/// Fills the whole native window with title, progress and volume, returns true if the window
+/// mode was changed. `dragging` is whether the window is being moved, kept between frames
+pub fn build(
+    ui: &imgui::Ui,
+    player_state: &PlayerState,
+    volume: &GainControl,
+    settings: &mut Settings,
+    muted_volume: &mut Option<f32>,
+    dragging: &mut bool,
+    requests: &mut Requests,
+) -> bool {
+    let mut changed = false;
+    Window::new(im_str!("Mini player"))
+        .position([0.0, 0.0], Condition::Always)
+        .size(ui.io().display_size, Condition::Always)
+        .no_decoration()
+        .movable(false)
+        .save_settings(false)
+        .build(ui, || {
+            let info = player_state.state();
+            let (progress, overlay) = match info.item.as_ref() {
+                Some(item) => {
+                    ui.text(ImString::new(item.name.as_str()));
+                    let time = ResourceTime::new(item);
+                    let timestamp = player_state.position();
+                    let position_us = time.position_us(timestamp);
+                    let position = format::format_timestamp((position_us / TIME_BASE) as i64);
+                    match time.duration_us {
+                        Some(duration_us) => (
+                            time.progress(timestamp).unwrap_or(0.0),
+                            format!(
+                                "{} / {}",
+                                position,
+                                format::format_timestamp((duration_us / TIME_BASE) as i64)
+                            ),
+                        ),
+                        None => (0.0, position),
+                    }
+                }
+                None => {
+                    ui.text(im_str!("-"));
+                    (0.0, String::new())
+                }
+            };
+            drop(info);
+            ProgressBar::new(progress)
+                .overlay_text(&ImString::new(overlay))
+                .build(ui);
+            GuiState::build_volume(volume, settings, muted_volume, ui);
+            if ui.button(im_str!("Expand"), [0.0, 0.0]) {
+                settings.mini_player.enabled = false;
+                changed = true;
+            }
+            ui.same_line(0.0);
+            changed |= ui.checkbox(
+                im_str!("Always on top"),
+                &mut settings.mini_player.always_on_top,
+            );
+
+            // Without decorations, dragging the background moves the native window
+            if ui.is_mouse_clicked(MouseButton::Left) {
+                *dragging = ui.is_window_hovered() && !ui.is_any_item_hovered();
+            }
+            if !ui.is_mouse_down(MouseButton::Left) {
+                *dragging = false;
+            }
+            requests.drag_window = *dragging;
+        });
+    changed
+}
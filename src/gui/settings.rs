@@ -0,0 +1,188 @@
+use std::path::PathBuf;
+
+use imgui::{ComboBox, Condition, ImString, Slider, Window};
+
+use crate::audio_client::{ResumeMode, TransitionMode};
+use crate::fonts::{self, FontSettings, UiFont};
+use crate::gui::PlayerState;
+use crate::notifications::NotificationSettings;
+use crate::recorder::RecordFormat;
+use crate::settings::Settings;
+
+pub struct SettingsWindow {
+    pub opened: bool,
+    recording_directory: ImString,
+    /// Fonts to load in the next frame
+    fonts: Option<FontSettings>,
+    /// Notification settings to pass to the notifier
+    notifications: Option<NotificationSettings>,
+}
+
+impl SettingsWindow {
+    pub fn new(settings: &Settings) -> Self {
+        let mut recording_directory = ImString::with_capacity(256);
+        recording_directory.push_str(&settings.recording.directory.to_string_lossy());
+        SettingsWindow {
+            opened: false,
+            recording_directory,
+            fonts: None,
+            notifications: None,
+        }
+    }
+
+    fn build_recording(&mut self, ui: &imgui::Ui, settings: &mut Settings) {
+        ui.text(im_str!("Recording"));
+        let recording = &mut settings.recording;
+        ui.radio_button(
+            im_str!("Ogg Opus"),
+            &mut recording.format,
+            RecordFormat::OggOpus,
+        );
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("WAV"), &mut recording.format, RecordFormat::Wav);
+        if ui
+            .input_text(im_str!("Directory"), &mut self.recording_directory)
+            .build()
+        {
+            recording.directory = PathBuf::from(self.recording_directory.to_str());
+        }
+    }
+
+    fn build_transition(ui: &imgui::Ui, settings: &mut Settings, player_state: &PlayerState) {
+        ui.text(im_str!("Transition between resources"));
+        let transition = &mut settings.transition;
+        let mut changed =
+            ui.radio_button(im_str!("None"), &mut transition.mode, TransitionMode::None);
+        ui.same_line(0.0);
+        changed |= ui.radio_button(im_str!("Fade"), &mut transition.mode, TransitionMode::Fade);
+        ui.same_line(0.0);
+        changed |= ui.radio_button(
+            im_str!("Crossfade"),
+            &mut transition.mode,
+            TransitionMode::Crossfade,
+        );
+        changed |= Slider::new(im_str!("Duration"))
+            .range(100..=5000)
+            .display_format(im_str!("%d ms"))
+            .build(ui, &mut transition.duration_ms);
+        if changed {
+            *player_state.transition() = *transition;
+        }
+    }
+
+    /// Font settings to apply, if they changed since the last call
+    pub fn take_fonts(&mut self) -> Option<FontSettings> {
+        self.fonts.take()
+    }
+
+    fn build_font(&mut self, ui: &imgui::Ui, settings: &mut Settings) {
+        ui.text(im_str!("Font"));
+        let font = &mut settings.font;
+        let names = UiFont::ALL
+            .iter()
+            .map(|font| ImString::new(font.name()))
+            .collect::<Vec<_>>();
+        let mut selected = UiFont::ALL
+            .iter()
+            .position(|f| *f == font.font)
+            .unwrap_or(0);
+        if ComboBox::new(im_str!("UI font")).build_simple_string(
+            ui,
+            &mut selected,
+            &names.iter().collect::<Vec<_>>(),
+        ) {
+            font.font = UiFont::ALL[selected];
+            self.fonts = Some(*font);
+        }
+        Slider::new(im_str!("Font size"))
+            .range(fonts::MIN_SIZE..=fonts::MAX_SIZE)
+            .display_format(im_str!("%.0f px"))
+            .build(ui, &mut font.size);
+        // Rebuilding the atlas is slow, so wait until the slider is released
+        if ui.is_item_deactivated_after_edit() {
+            self.fonts = Some(*font);
+        }
+    }
+
+    /// Notification settings to apply, if they changed since the last call
+    pub fn take_notifications(&mut self) -> Option<NotificationSettings> {
+        self.notifications.take()
+    }
+
+    fn build_notifications(&mut self, ui: &imgui::Ui, settings: &mut Settings) {
+        ui.text(im_str!("Notifications"));
+        let notifications = &mut settings.notifications;
+        let mut changed = ui.checkbox(
+            im_str!("Notify on track change"),
+            &mut notifications.enabled,
+        );
+        changed |= ui.checkbox(
+            im_str!("Also when focused"),
+            &mut notifications.when_focused,
+        );
+        changed |= Slider::new(im_str!("Minimum interval"))
+            .range(0..=300)
+            .display_format(im_str!("%d s"))
+            .build(ui, &mut notifications.min_interval_s);
+        if changed {
+            self.notifications = Some(*notifications);
+        }
+    }
+
+    fn build_pause(ui: &imgui::Ui, settings: &mut Settings, player_state: &PlayerState) {
+        ui.text(im_str!("Pause and rewind"));
+        let pause = &mut settings.pause;
+        ui.text(im_str!("Remote resume"));
+        ui.same_line(0.0);
+        ui.radio_button(im_str!("Live"), &mut pause.resume_mode, ResumeMode::Live);
+        ui.same_line(0.0);
+        ui.radio_button(
+            im_str!("Time-shift"),
+            &mut pause.resume_mode,
+            ResumeMode::TimeShift,
+        );
+        if Slider::new(im_str!("Pause buffer"))
+            .range(1..=600)
+            .display_format(im_str!("%d s"))
+            .build(ui, &mut pause.max_buffer_s)
+        {
+            player_state.set_max_pause_seconds(pause.max_buffer_s);
+        }
+        if Slider::new(im_str!("Rewind history"))
+            .range(10..=1800)
+            .display_format(im_str!("%d s"))
+            .build(ui, &mut pause.rewind_s)
+        {
+            player_state.set_max_history_seconds(pause.rewind_s);
+        }
+    }
+
+    pub fn build(
+        &mut self,
+        ui: &imgui::Ui,
+        layout: Condition,
+        settings: &mut Settings,
+        player_state: &PlayerState,
+    ) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Settings"))
+            .position([420.0, 30.0], layout)
+            .size([400.0, 360.0], layout)
+            .opened(&mut opened)
+            .build(ui, || {
+                Self::build_transition(ui, settings, player_state);
+                ui.separator();
+                Self::build_pause(ui, settings, player_state);
+                ui.separator();
+                self.build_recording(ui, settings);
+                ui.separator();
+                self.build_notifications(ui, settings);
+                ui.separator();
+                self.build_font(ui, settings);
+            });
+        self.opened = opened;
+    }
+}
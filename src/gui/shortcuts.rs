@@ -0,0 +1,245 @@
+use glutin::VirtualKeyCode;
+use imgui::{Condition, Window};
+
+use crate::settings::ShortcutSettings;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Connects if disconnected, otherwise disconnects
+    Connect,
+    /// Pauses or resumes while connected
+    Pause,
+    VolumeUp,
+    VolumeDown,
+    Mute,
+    Diagnostics,
+    Help,
+}
+
+const KEYS: &[(&str, VirtualKeyCode)] = &[
+    ("A", VirtualKeyCode::A),
+    ("B", VirtualKeyCode::B),
+    ("C", VirtualKeyCode::C),
+    ("D", VirtualKeyCode::D),
+    ("E", VirtualKeyCode::E),
+    ("F", VirtualKeyCode::F),
+    ("G", VirtualKeyCode::G),
+    ("H", VirtualKeyCode::H),
+    ("I", VirtualKeyCode::I),
+    ("J", VirtualKeyCode::J),
+    ("K", VirtualKeyCode::K),
+    ("L", VirtualKeyCode::L),
+    ("M", VirtualKeyCode::M),
+    ("N", VirtualKeyCode::N),
+    ("O", VirtualKeyCode::O),
+    ("P", VirtualKeyCode::P),
+    ("Q", VirtualKeyCode::Q),
+    ("R", VirtualKeyCode::R),
+    ("S", VirtualKeyCode::S),
+    ("T", VirtualKeyCode::T),
+    ("U", VirtualKeyCode::U),
+    ("V", VirtualKeyCode::V),
+    ("W", VirtualKeyCode::W),
+    ("X", VirtualKeyCode::X),
+    ("Y", VirtualKeyCode::Y),
+    ("Z", VirtualKeyCode::Z),
+    ("0", VirtualKeyCode::Key0),
+    ("1", VirtualKeyCode::Key1),
+    ("2", VirtualKeyCode::Key2),
+    ("3", VirtualKeyCode::Key3),
+    ("4", VirtualKeyCode::Key4),
+    ("5", VirtualKeyCode::Key5),
+    ("6", VirtualKeyCode::Key6),
+    ("7", VirtualKeyCode::Key7),
+    ("8", VirtualKeyCode::Key8),
+    ("9", VirtualKeyCode::Key9),
+    ("F1", VirtualKeyCode::F1),
+    ("F2", VirtualKeyCode::F2),
+    ("F3", VirtualKeyCode::F3),
+    ("F4", VirtualKeyCode::F4),
+    ("F5", VirtualKeyCode::F5),
+    ("F6", VirtualKeyCode::F6),
+    ("F7", VirtualKeyCode::F7),
+    ("F8", VirtualKeyCode::F8),
+    ("F9", VirtualKeyCode::F9),
+    ("F10", VirtualKeyCode::F10),
+    ("F11", VirtualKeyCode::F11),
+    ("F12", VirtualKeyCode::F12),
+    ("Space", VirtualKeyCode::Space),
+    ("Enter", VirtualKeyCode::Return),
+    ("Escape", VirtualKeyCode::Escape),
+    ("Tab", VirtualKeyCode::Tab),
+    ("Up", VirtualKeyCode::Up),
+    ("Down", VirtualKeyCode::Down),
+    ("Left", VirtualKeyCode::Left),
+    ("Right", VirtualKeyCode::Right),
+    ("Home", VirtualKeyCode::Home),
+    ("End", VirtualKeyCode::End),
+    ("PageUp", VirtualKeyCode::PageUp),
+    ("PageDown", VirtualKeyCode::PageDown),
+];
+
+/// Key with the modifiers that have to be held
+#[derive(Clone, Copy, PartialEq)]
+pub struct Shortcut {
+    ctrl: bool,
+    shift: bool,
+    alt: bool,
+    key: VirtualKeyCode,
+}
+
+impl Shortcut {
+    /// Parses key names like `Space`, `M` or `Ctrl+D`, ignoring case
+    pub fn parse(text: &str) -> Option<Shortcut> {
+        let mut parts = text.split('+').map(str::trim).collect::<Vec<_>>();
+        let name = parts.pop()?;
+        let key = KEYS
+            .iter()
+            .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))?
+            .1;
+        let mut shortcut = Shortcut {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            key,
+        };
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => shortcut.ctrl = true,
+                "shift" => shortcut.shift = true,
+                "alt" => shortcut.alt = true,
+                _ => return None,
+            }
+        }
+        Some(shortcut)
+    }
+
+    fn pressed(&self, ui: &imgui::Ui) -> bool {
+        let io = ui.io();
+        io.key_ctrl == self.ctrl
+            && io.key_shift == self.shift
+            && io.key_alt == self.alt
+            // imgui-winit-support indexes the keys by their winit key code
+            && ui.is_key_pressed(self.key as u32)
+    }
+}
+
+struct Binding {
+    action: Action,
+    description: &'static str,
+    name: String,
+    shortcut: Shortcut,
+}
+
+/// Configured shortcuts and the help overlay listing them. Shortcuts only work while the
+/// client window has focus, there are no global hotkeys
+pub struct Shortcuts {
+    bindings: Vec<Binding>,
+    pub help_opened: bool,
+}
+
+impl Shortcuts {
+    pub fn new(settings: &ShortcutSettings) -> Self {
+        let defaults = ShortcutSettings::default();
+        let configured = [
+            (
+                Action::Connect,
+                "Connect or disconnect",
+                &settings.connect,
+                defaults.connect,
+            ),
+            (
+                Action::Pause,
+                "Pause or resume",
+                &settings.pause,
+                defaults.pause,
+            ),
+            (
+                Action::VolumeUp,
+                "Volume up",
+                &settings.volume_up,
+                defaults.volume_up,
+            ),
+            (
+                Action::VolumeDown,
+                "Volume down",
+                &settings.volume_down,
+                defaults.volume_down,
+            ),
+            (Action::Mute, "Mute", &settings.mute, defaults.mute),
+            (
+                Action::Diagnostics,
+                "Diagnostics",
+                &settings.diagnostics,
+                defaults.diagnostics,
+            ),
+            (
+                Action::Help,
+                "Show shortcuts",
+                &settings.help,
+                defaults.help,
+            ),
+        ];
+        let bindings = configured
+            .iter()
+            .map(|(action, description, name, default)| {
+                let (name, shortcut) = match Shortcut::parse(name) {
+                    Some(shortcut) => (name.to_string(), shortcut),
+                    None => {
+                        warn!("Invalid shortcut \"{}\", using \"{}\"", name, default);
+                        (default.clone(), Shortcut::parse(default).unwrap())
+                    }
+                };
+                Binding {
+                    action: *action,
+                    description,
+                    name,
+                    shortcut,
+                }
+            })
+            .collect();
+        Shortcuts {
+            bindings,
+            help_opened: false,
+        }
+    }
+
+    /// Actions whose shortcut was pressed in this frame, none while typing into a text field
+    pub fn pressed(&self, ui: &imgui::Ui) -> Vec<Action> {
+        if ui.io().want_text_input {
+            return Vec::new();
+        }
+        self.bindings
+            .iter()
+            .filter(|binding| binding.shortcut.pressed(ui))
+            .map(|binding| binding.action)
+            .collect()
+    }
+
+    pub fn build_help(&mut self, ui: &imgui::Ui) {
+        if !self.help_opened {
+            return;
+        }
+        let [width, height] = ui.io().display_size;
+        let bindings = &self.bindings;
+        Window::new(im_str!("Shortcuts"))
+            .position([width / 2.0, height / 2.0], Condition::Always)
+            .position_pivot([0.5, 0.5])
+            .bg_alpha(0.85)
+            .always_auto_resize(true)
+            .collapsible(false)
+            .resizable(false)
+            .movable(false)
+            .opened(&mut self.help_opened)
+            .build(ui, || {
+                ui.columns(2, im_str!("shortcuts"), false);
+                for binding in bindings {
+                    ui.text(binding.description);
+                    ui.next_column();
+                    ui.text(&binding.name);
+                    ui.next_column();
+                }
+                ui.columns(1, im_str!("shortcuts"), false);
+            });
+    }
+}
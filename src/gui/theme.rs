@@ -0,0 +1,128 @@
+use imgui::{ColorEdit, ComboBox, Condition, ImString, StyleColor, Window};
+
+use crate::settings::Settings;
+use crate::theme::{self, Theme};
+
+pub struct ThemeWindow {
+    pub opened: bool,
+    themes: Vec<Theme>,
+    selected: usize,
+    /// Name the edited theme is saved under
+    name: ImString,
+    /// Theme to apply in the next frame
+    pending: Option<Theme>,
+}
+
+impl ThemeWindow {
+    pub fn new(settings: &Settings) -> Self {
+        let themes = Theme::load_all();
+        let selected = themes
+            .iter()
+            .position(|theme| theme.name == settings.theme)
+            .unwrap_or(0);
+        let mut window = ThemeWindow {
+            opened: false,
+            themes,
+            selected,
+            name: ImString::with_capacity(64),
+            pending: None,
+        };
+        window.select(selected);
+        window
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index;
+        let theme = &self.themes[index];
+        self.name.clear();
+        self.name.push_str(&theme.name);
+        self.pending = Some(theme.clone());
+    }
+
+    /// Theme to apply, if it changed since the last call
+    pub fn take_theme(&mut self) -> Option<Theme> {
+        self.pending.take()
+    }
+
+    fn build_editor(&mut self, ui: &imgui::Ui) {
+        let style = ui.clone_style();
+        let theme = &mut self.themes[self.selected];
+        for color in StyleColor::VARIANTS.iter() {
+            let name = theme::color_name(*color);
+            let mut value = theme::linear_to_gamma(style[*color]);
+            if ColorEdit::new(&ImString::new(name.as_str()), &mut value)
+                .alpha(true)
+                .inputs(false)
+                .build(ui)
+            {
+                theme.colors.insert(name, value);
+                self.pending = Some(theme.clone());
+            }
+        }
+    }
+
+    fn build_contents(&mut self, ui: &imgui::Ui, settings: &mut Settings) {
+        let names = self
+            .themes
+            .iter()
+            .map(|theme| ImString::new(theme.name.as_str()))
+            .collect::<Vec<_>>();
+        let mut selected = self.selected;
+        if ComboBox::new(im_str!("Theme")).build_simple_string(
+            ui,
+            &mut selected,
+            &names.iter().collect::<Vec<_>>(),
+        ) {
+            self.select(selected);
+            settings.theme = self.themes[selected].name.clone();
+        }
+        if ui.button(im_str!("Reload theme files"), [0.0, 0.0]) {
+            let name = self.themes[self.selected].name.clone();
+            self.themes = Theme::load_all();
+            let index = self
+                .themes
+                .iter()
+                .position(|theme| theme.name == name)
+                .unwrap_or(0);
+            self.select(index);
+        }
+
+        ui.separator();
+        ui.input_text(im_str!("Name"), &mut self.name).build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Save"), [0.0, 0.0]) && !self.name.to_str().is_empty() {
+            let mut theme = self.themes[self.selected].clone();
+            theme.name = self.name.to_str().to_owned();
+            theme.save();
+            // The saved copy replaces a theme of the same name
+            let index = match self.themes.iter().position(|t| t.name == theme.name) {
+                Some(index) => {
+                    self.themes[index] = theme;
+                    index
+                }
+                None => {
+                    self.themes.push(theme);
+                    self.themes.len() - 1
+                }
+            };
+            self.select(index);
+            settings.theme = self.themes[index].name.clone();
+        }
+        if imgui::CollapsingHeader::new(im_str!("Colors")).build(ui) {
+            self.build_editor(ui);
+        }
+    }
+
+    pub fn build(&mut self, ui: &imgui::Ui, layout: Condition, settings: &mut Settings) {
+        if !self.opened {
+            return;
+        }
+        let mut opened = self.opened;
+        Window::new(im_str!("Theme"))
+            .position([640.0, 30.0], layout)
+            .size([360.0, 400.0], layout)
+            .opened(&mut opened)
+            .build(ui, || self.build_contents(ui, settings));
+        self.opened = opened;
+    }
+}
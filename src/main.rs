@@ -4,23 +4,63 @@ extern crate imgui;
 extern crate log;
 
 use crate::audio_client::AudioClient;
-use crate::audio_stream::create_stream;
+use crate::audio_stream::{CpalSink, OutputSink, SourceReader};
+use crate::control::ControlApi;
+use crate::effects::{effect_chain, linear_to_db, EffectControls, GainControl};
+use crate::equalizer::EqualizerControl;
+use crate::file_source::FileSource;
+use crate::gfx_system::Requests;
 use crate::gui::{GuiState, PlayerState};
-use cpal::traits::StreamTrait;
-use std::sync::Arc;
+use crate::metrics::MetricsSources;
+use crate::notifications::Notifier;
+use crate::options::Options;
+use crate::output::{NullSink, Pace};
+use crate::recorder::Recorder;
+use crate::session::SessionWriter;
+use crate::settings::Settings;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 
 mod audio_client;
 mod audio_socket;
 mod audio_stream;
+mod control;
+mod effects;
+mod equalizer;
+mod file_source;
+mod fonts;
 mod format;
 mod gfx_system;
 mod gui;
+mod metrics;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod notifications;
+mod now_playing;
+mod ogg_opus;
+mod options;
+mod output;
+mod playback_clock;
+mod recorder;
+mod session;
+mod settings;
 mod single_buffer_sender;
+mod statistics;
+#[cfg(test)]
+mod test_support;
+#[cfg(test)]
+mod tests;
+mod theme;
 mod token;
 
-async fn run_gui(mut state: GuiState) {
-    let system = gfx_system::init("Leierkasten Client");
-    system.main_loop(|_, ui| state.build(ui)).await;
+async fn run_gui(state: &mut GuiState, window_focused: Arc<AtomicBool>) {
+    let mut system = gfx_system::init("Leierkasten Client", state.settings(), window_focused);
+    let mut requests = Requests::default();
+    state.take_requests(&mut requests);
+    system.apply(requests);
+    system
+        .main_loop(|_, ui, requests, textures| state.build(ui, requests, textures))
+        .await;
 }
 
 const ADDRESS: &str = "ws://localhost:2020/";
@@ -31,17 +71,108 @@ async fn main() -> Result<(), std::io::Error> {
         std::env::var("RUST_LOG").map_err(|_| std::env::set_var("RUST_LOG", "leierkasten_client"));
     env_logger::init();
 
+    let options = Options::parse();
+    let settings = Settings::load();
+
     let (sender, receiver) = tokio::sync::mpsc::channel(5);
-    let state = Arc::new(PlayerState::new());
-    let client = AudioClient::new(receiver, state.clone());
-    let stream = create_stream(client);
+    let state = Arc::new(PlayerState::new(settings.transition));
+    state.set_max_pause_seconds(settings.pause.max_buffer_s);
+    state.set_max_history_seconds(settings.pause.rewind_s);
+    let effect_controls = EffectControls {
+        equalizer: Arc::new(EqualizerControl::new(settings.equalizer.clone())),
+        volume: Arc::new(GainControl::new(linear_to_db(settings.volume))),
+    };
+    let volume = effect_controls.volume.clone();
+    let (effects, effect_chain) = effect_chain(volume.clone());
+    let (recorder, recorder_tap) = Recorder::spawn();
+    let window_focused = Arc::new(AtomicBool::new(true));
+    let (notifier, notification_tap) =
+        Notifier::spawn(settings.notifications, window_focused.clone());
+    let statistics = state.statistics().clone();
+    let clock = state.clock().clone();
+    let (reader, file) = match options.play.as_ref() {
+        Some(path) => {
+            let (source, control) = FileSource::open(path, state.clone())?;
+            info!("Playing {}", path.display());
+            (
+                SourceReader::new(source, effects, statistics).with_clock(clock),
+                Some(control),
+            )
+        }
+        None => {
+            let client = AudioClient::new(receiver, state.clone(), recorder_tap)
+                .with_notifications(notification_tap);
+            let reader = SourceReader::new(client, effects, statistics).with_clock(clock);
+            (reader, None)
+        }
+    };
+    let mut output: Box<dyn OutputSink> = if options.null_output {
+        Box::new(NullSink::new(Pace::RealTime))
+    } else {
+        Box::new(CpalSink::default())
+    };
+
+    let mut context = GuiState::new(
+        sender,
+        state.clone(),
+        effect_chain,
+        effect_controls,
+        recorder,
+        settings,
+    );
+    context.set_notifier(notifier);
+    if let Some(control) = file {
+        context.set_file(control);
+    }
+    if let Some(path) = options.replay_session.clone() {
+        context.set_replay(path);
+    } else if let Some(path) = options.record_session.as_ref() {
+        match SessionWriter::create(path) {
+            Ok(writer) => {
+                info!("Recording session to {}", path.display());
+                context.set_session_recording(Arc::new(Mutex::new(writer)));
+            }
+            Err(err) => warn!("Failed to create {}: {}", path.display(), err),
+        }
+    }
 
-    let context = GuiState::new(sender, state);
+    let (commands, command_receiver) = std::sync::mpsc::channel();
+    context.set_control(command_receiver);
+    if let Some(address) = options.control_address() {
+        let api = ControlApi {
+            commands: commands.clone(),
+            player_state: state.clone(),
+            socket_state: context.socket_state(),
+            volume: volume.clone(),
+        };
+        match control::start(address, api) {
+            Ok(address) => info!("Serving control API on http://{}/", address),
+            Err(err) => warn!("Failed to start control API on {}: {}", address, err),
+        }
+    }
+    #[cfg(target_os = "linux")]
+    let _mpris = mpris::Mpris::spawn(mpris::MprisState {
+        player_state: state.clone(),
+        socket_state: context.socket_state(),
+        commands,
+        volume,
+    });
+    if let Some(address) = options.metrics {
+        let sources = MetricsSources {
+            player_state: state,
+            socket_state: context.socket_state(),
+        };
+        match metrics::start(address, sources) {
+            Ok(address) => info!("Serving metrics on http://{}/metrics", address),
+            Err(err) => warn!("Failed to start metrics server on {}: {}", address, err),
+        }
+    }
 
     info!("Playing stream");
-    stream.play().unwrap();
+    output.start(reader);
 
-    run_gui(context).await;
+    run_gui(&mut context, window_focused).await;
+    context.exit();
 
     info!("Exiting");
     Ok(())
@@ -0,0 +1,179 @@
+//! Prometheus metrics endpoint for monitoring unattended clients
+
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+
+use crate::audio_client::{SAMPLES_PER_FRAME, SAMPLE_RATE, TIME_BASE};
+use crate::audio_socket::State;
+use crate::gui::PlayerState;
+use crate::now_playing;
+
+/// State read by the endpoint on every scrape
+#[derive(Clone)]
+pub struct MetricsSources {
+    pub player_state: Arc<PlayerState>,
+    pub socket_state: Arc<Mutex<State>>,
+}
+
+/// Escapes a label value as required by the text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Renders all metrics in the Prometheus text format
+pub fn render(sources: &MetricsSources) -> String {
+    let mut out = String::new();
+    let state = &sources.player_state;
+    let statistics = state.statistics();
+
+    let current = sources.socket_state.lock().unwrap().as_str();
+    let _ = writeln!(
+        out,
+        "# HELP leierkasten_connection_state Current state of the server connection"
+    );
+    let _ = writeln!(out, "# TYPE leierkasten_connection_state gauge");
+    for name in State::ALL.iter().map(State::as_str) {
+        let _ = writeln!(
+            out,
+            "leierkasten_connection_state{{state=\"{}\"}} {}",
+            name,
+            (name == current) as u8
+        );
+    }
+
+    let frame_seconds = SAMPLES_PER_FRAME as f64 / SAMPLE_RATE as f64;
+    metric(
+        &mut out,
+        "leierkasten_buffer_seconds",
+        "gauge",
+        "Audio buffered ahead of playback",
+        state.buffer() as f64 * frame_seconds,
+    );
+    metric(
+        &mut out,
+        "leierkasten_target_buffer_seconds",
+        "gauge",
+        "Audio buffered before playback starts",
+        state.target_buffer() as f64 * frame_seconds,
+    );
+    metric(
+        &mut out,
+        "leierkasten_buffering",
+        "gauge",
+        "Whether playback is waiting for the buffer to fill",
+        state.state().buffering as u8,
+    );
+    metric(
+        &mut out,
+        "leierkasten_paused",
+        "gauge",
+        "Whether playback is paused while staying connected",
+        state.is_paused() as u8,
+    );
+    metric(
+        &mut out,
+        "leierkasten_underruns_total",
+        "counter",
+        "Times the output ran out of audio",
+        statistics.underruns(),
+    );
+    metric(
+        &mut out,
+        "leierkasten_reconnects_total",
+        "counter",
+        "Connections to the server after the first one",
+        statistics.reconnects(),
+    );
+    metric(
+        &mut out,
+        "leierkasten_decode_errors_total",
+        "counter",
+        "Opus packets that failed to decode",
+        statistics.decode_errors(),
+    );
+    metric(
+        &mut out,
+        "leierkasten_packets_received_total",
+        "counter",
+        "Audio packets received from the server",
+        statistics.packets(),
+    );
+    metric(
+        &mut out,
+        "leierkasten_received_bytes_total",
+        "counter",
+        "Bytes of audio received from the server",
+        statistics.bytes(),
+    );
+    metric(
+        &mut out,
+        "leierkasten_position_seconds",
+        "gauge",
+        "Playback position in the current resource",
+        now_playing::position_us(state.state().item.as_ref(), state.position()) as f64
+            / TIME_BASE as f64,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP leierkasten_resource_info Name of the current resource"
+    );
+    let _ = writeln!(out, "# TYPE leierkasten_resource_info gauge");
+    if let Some(item) = state.state().item.as_ref() {
+        let _ = writeln!(
+            out,
+            "leierkasten_resource_info{{name=\"{}\"}} 1",
+            escape_label(&item.name)
+        );
+    }
+    out
+}
+
+fn handle(request: Request<Body>, sources: &MetricsSources) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::from("Not found\n"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let mut response = Response::new(Body::from(render(sources)));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    response
+}
+
+/// Starts serving `/metrics` on `address`, returns the bound address
+pub fn start(address: SocketAddr, sources: MetricsSources) -> hyper::Result<SocketAddr> {
+    let make_service = make_service_fn(move |_| {
+        let sources = sources.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(request, &sources);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&address)?.serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            warn!("Metrics server failed: {}", err);
+        }
+    });
+    Ok(address)
+}
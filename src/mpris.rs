@@ -0,0 +1,303 @@
+//! MPRIS2 D-Bus service so desktop media keys and widgets can control the client
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use dbus::arg::{PropMap, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender as _};
+use dbus::message::{MatchRule, SignalArgs};
+use dbus_crossroads::{Crossroads, IfaceToken};
+
+use crate::audio_socket::State;
+use crate::control::ControlCommand;
+use crate::effects::{db_to_linear, GainControl};
+use crate::gui::PlayerState;
+use crate::now_playing::{self, ResourceTime};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.leierkasten";
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// State read and controlled by the service
+#[derive(Clone)]
+pub struct MprisState {
+    pub player_state: Arc<PlayerState>,
+    pub socket_state: Arc<Mutex<State>>,
+    pub commands: Sender<ControlCommand>,
+    pub volume: Arc<GainControl>,
+}
+
+/// Values that are announced with `PropertiesChanged` when they change
+#[derive(Clone, PartialEq)]
+struct Snapshot {
+    playing: bool,
+    paused: bool,
+    title: Option<String>,
+    resource_id: u64,
+    length_us: Option<u64>,
+    volume: f64,
+}
+
+impl MprisState {
+    fn snapshot(&self) -> Snapshot {
+        let playing = matches!(*self.socket_state.lock().unwrap(), State::Connected);
+        let info = self.player_state.state();
+        let item = info.item.as_ref();
+        Snapshot {
+            playing,
+            paused: self.player_state.is_paused(),
+            title: item.map(|item| item.name.clone()),
+            resource_id: self.player_state.resource_id(),
+            length_us: item.and_then(|item| ResourceTime::new(item).duration_us),
+            volume: db_to_linear(self.volume.gain_db()) as f64,
+        }
+    }
+
+    fn send(&self, command: ControlCommand) {
+        let _ = self.commands.send(command);
+    }
+
+    fn position_us(&self) -> i64 {
+        let timestamp = self.player_state.position();
+        now_playing::position_us(self.player_state.state().item.as_ref(), timestamp) as i64
+    }
+}
+
+impl Snapshot {
+    fn playback_status(&self) -> String {
+        match (self.playing, self.paused) {
+            (true, false) => "Playing",
+            (true, true) => "Paused",
+            (false, _) => "Stopped",
+        }
+        .into()
+    }
+
+    fn metadata(&self) -> PropMap {
+        let mut metadata = PropMap::new();
+        let track_id = match self.title {
+            Some(_) => format!("/org/leierkasten/track/{}", self.resource_id),
+            None => NO_TRACK.into(),
+        };
+        metadata.insert(
+            "mpris:trackid".into(),
+            Variant(Box::new(dbus::Path::from(track_id))),
+        );
+        if let Some(title) = self.title.as_ref() {
+            metadata.insert("xesam:title".into(), Variant(Box::new(title.clone())));
+        }
+        if let Some(length_us) = self.length_us {
+            metadata.insert("mpris:length".into(), Variant(Box::new(length_us as i64)));
+        }
+        metadata
+    }
+}
+
+fn register_root(cr: &mut Crossroads) -> IfaceToken<MprisState> {
+    cr.register(ROOT_INTERFACE, |b| {
+        b.method("Raise", (), (), |_, _: &mut MprisState, ()| Ok(()));
+        b.method("Quit", (), (), |_, _: &mut MprisState, ()| Ok(()));
+        b.property("CanQuit").get(|_, _| Ok(false));
+        b.property("CanRaise").get(|_, _| Ok(false));
+        b.property("HasTrackList").get(|_, _| Ok(false));
+        b.property("Identity")
+            .get(|_, _| Ok("Leierkasten".to_string()));
+        b.property("SupportedUriSchemes")
+            .get(|_, _| Ok(Vec::<String>::new()));
+        b.property("SupportedMimeTypes")
+            .get(|_, _| Ok(Vec::<String>::new()));
+    })
+}
+
+fn register_player(cr: &mut Crossroads) -> IfaceToken<MprisState> {
+    cr.register(PLAYER_INTERFACE, |b| {
+        b.method("Play", (), (), |_, state: &mut MprisState, ()| {
+            if state.snapshot().paused {
+                state.send(ControlCommand::Resume);
+            } else {
+                state.send(ControlCommand::Connect);
+            }
+            Ok(())
+        });
+        b.method("Pause", (), (), |_, state: &mut MprisState, ()| {
+            state.send(ControlCommand::Pause);
+            Ok(())
+        });
+        b.method("Stop", (), (), |_, state: &mut MprisState, ()| {
+            state.send(ControlCommand::Disconnect);
+            Ok(())
+        });
+        b.method("PlayPause", (), (), |_, state: &mut MprisState, ()| {
+            let snapshot = state.snapshot();
+            if snapshot.paused {
+                state.send(ControlCommand::Resume);
+            } else if snapshot.playing {
+                state.send(ControlCommand::Pause);
+            } else {
+                state.send(ControlCommand::Connect);
+            }
+            Ok(())
+        });
+        b.method("Next", (), (), |_, _: &mut MprisState, ()| Ok(()));
+        b.method("Previous", (), (), |_, _: &mut MprisState, ()| Ok(()));
+        b.method(
+            "Seek",
+            ("Offset",),
+            (),
+            |_, _: &mut MprisState, (_,): (i64,)| Ok(()),
+        );
+        b.method(
+            "SetPosition",
+            ("TrackId", "Position"),
+            (),
+            |_, _: &mut MprisState, (_, _): (dbus::Path<'static>, i64)| Ok(()),
+        );
+        b.method(
+            "OpenUri",
+            ("Uri",),
+            (),
+            |_, _: &mut MprisState, (_,): (String,)| Ok(()),
+        );
+
+        b.property("PlaybackStatus")
+            .get(|_, state: &mut MprisState| Ok(state.snapshot().playback_status()));
+        b.property("Metadata")
+            .get(|_, state: &mut MprisState| Ok(state.snapshot().metadata()));
+        b.property("Position")
+            .emits_changed_false()
+            .get(|_, state: &mut MprisState| Ok(state.position_us()));
+        b.property("Volume")
+            .get(|_, state: &mut MprisState| Ok(state.snapshot().volume))
+            .set(|_, state: &mut MprisState, volume: f64| {
+                let volume = volume.clamp(0.0, 1.0);
+                state.send(ControlCommand::SetVolume(volume as f32));
+                Ok(Some(volume))
+            });
+        b.property("Rate").get(|_, _| Ok(1.0));
+        b.property("MinimumRate").get(|_, _| Ok(1.0));
+        b.property("MaximumRate").get(|_, _| Ok(1.0));
+        b.property("CanGoNext").get(|_, _| Ok(false));
+        b.property("CanGoPrevious").get(|_, _| Ok(false));
+        b.property("CanPlay").get(|_, _| Ok(true));
+        b.property("CanPause").get(|_, _| Ok(true));
+        b.property("CanSeek").get(|_, _| Ok(false));
+        b.property("CanControl").get(|_, _| Ok(true));
+    })
+}
+
+fn changed_properties(old: &Snapshot, new: &Snapshot) -> PropMap {
+    let mut changed = PropMap::new();
+    if old.playing != new.playing || old.paused != new.paused {
+        changed.insert(
+            "PlaybackStatus".into(),
+            Variant(Box::new(new.playback_status())),
+        );
+    }
+    if old.title != new.title
+        || old.resource_id != new.resource_id
+        || old.length_us != new.length_us
+    {
+        changed.insert("Metadata".into(), Variant(Box::new(new.metadata())));
+    }
+    if old.volume != new.volume {
+        changed.insert("Volume".into(), Variant(Box::new(new.volume)));
+    }
+    changed
+}
+
+fn serve(
+    connection: Connection,
+    state: MprisState,
+    running: &AtomicBool,
+) -> Result<(), dbus::Error> {
+    connection.request_name(BUS_NAME, false, true, false)?;
+    let mut cr = Crossroads::new();
+    let root = register_root(&mut cr);
+    let player = register_player(&mut cr);
+    cr.insert(PATH, &[root, player], state.clone());
+    connection.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |message, connection| {
+            let _ = cr.handle_message(message, connection);
+            true
+        }),
+    );
+
+    let path = dbus::Path::from(PATH);
+    let mut last = state.snapshot();
+    while running.load(Acquire) {
+        connection.process(Duration::from_millis(100))?;
+        let snapshot = state.snapshot();
+        if snapshot == last {
+            continue;
+        }
+        let signal = PropertiesPropertiesChanged {
+            interface_name: PLAYER_INTERFACE.into(),
+            changed_properties: changed_properties(&last, &snapshot),
+            invalidated_properties: Vec::new(),
+        };
+        let _ = connection.send(signal.to_emit_message(&path));
+        last = snapshot;
+    }
+    Ok(())
+}
+
+/// Runs the service on its own thread until dropped
+pub struct Mpris {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Mpris {
+    /// Registers the service on the session bus
+    pub fn spawn(state: MprisState) -> Mpris {
+        Self::spawn_with(state, Connection::new_session)
+    }
+
+    /// Registers the service on a private bus at `address`
+    #[cfg(test)]
+    pub fn spawn_on(address: String, state: MprisState) -> Mpris {
+        Self::spawn_with(state, move || {
+            let mut channel = dbus::channel::Channel::open_private(&address)?;
+            channel.register()?;
+            Ok(Connection::from(channel))
+        })
+    }
+
+    fn spawn_with<F>(state: MprisState, connect: F) -> Mpris
+    where
+        F: FnOnce() -> Result<Connection, dbus::Error> + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let running = running.clone();
+            std::thread::spawn(move || {
+                let res = connect().and_then(|connection| serve(connection, state, &running));
+                if let Err(err) = res {
+                    warn!("MPRIS service failed: {}", err);
+                }
+            })
+        };
+        Mpris {
+            running,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Mpris {
+    fn drop(&mut self) {
+        self.running.store(false, Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Desktop notifications when a new resource starts playing
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Acquire;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio_client::TIME_BASE;
+use crate::audio_socket::StreamStartMessage;
+use crate::format;
+use crate::now_playing::ResourceTime;
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationSettings {
+    pub enabled: bool,
+    /// Also notify while the client window has focus
+    pub when_focused: bool,
+    /// Minimum time between two notifications, the latest change is shown after it
+    pub min_interval_s: u32,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            enabled: false,
+            when_focused: false,
+            min_interval_s: 10,
+        }
+    }
+}
+
+struct Track {
+    title: String,
+    duration_us: Option<u64>,
+    started: Instant,
+}
+
+impl Track {
+    /// A track that ended while its notification was held back isn't shown anymore
+    fn is_finished(&self) -> bool {
+        matches!(self.duration_us, Some(duration_us)
+            if self.started.elapsed() >= Duration::from_micros(duration_us))
+    }
+}
+
+enum NotifierMessage {
+    Track(Track),
+    Shutdown,
+}
+
+/// Audio thread side of the notifier, sending never blocks
+#[derive(Clone)]
+pub struct NotificationTap {
+    sender: Sender<NotifierMessage>,
+}
+
+impl NotificationTap {
+    pub fn track_changed(&self, message: &StreamStartMessage) {
+        let _ = self.sender.send(NotifierMessage::Track(Track {
+            title: message.name.clone(),
+            duration_us: ResourceTime::new(message).duration_us,
+            started: Instant::now(),
+        }));
+    }
+}
+
+/// Gui side of the notifier
+pub struct Notifier {
+    sender: Sender<NotifierMessage>,
+    settings: Arc<Mutex<NotificationSettings>>,
+    thread: JoinHandle<()>,
+}
+
+impl Notifier {
+    /// Starts the notification thread, `focused` tells whether the client window has focus
+    pub fn spawn(
+        settings: NotificationSettings,
+        focused: Arc<AtomicBool>,
+    ) -> (Notifier, NotificationTap) {
+        Self::spawn_with(settings, focused, Desktop::default())
+    }
+
+    /// Like `spawn`, showing the notifications on `desktop`
+    pub fn spawn_with<D: ShowNotification>(
+        settings: NotificationSettings,
+        focused: Arc<AtomicBool>,
+        desktop: D,
+    ) -> (Notifier, NotificationTap) {
+        let (sender, receiver) = channel();
+        let settings = Arc::new(Mutex::new(settings));
+        let thread = NotificationThread {
+            receiver,
+            settings: settings.clone(),
+            focused,
+            pending: None,
+            last_shown: None,
+            desktop,
+        };
+        let thread = std::thread::spawn(move || thread.run());
+        let notifier = Notifier {
+            sender: sender.clone(),
+            settings,
+            thread,
+        };
+        (notifier, NotificationTap { sender })
+    }
+
+    pub fn configure(&self, settings: NotificationSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    pub fn shutdown(self) {
+        let _ = self.sender.send(NotifierMessage::Shutdown);
+        let _ = self.thread.join();
+    }
+}
+
+/// Where notifications are shown, the desktop outside of tests
+pub trait ShowNotification: Send + 'static {
+    fn show(&mut self, summary: &str, body: &str);
+}
+
+struct NotificationThread<D> {
+    receiver: Receiver<NotifierMessage>,
+    settings: Arc<Mutex<NotificationSettings>>,
+    focused: Arc<AtomicBool>,
+    /// Latest track change that wasn't shown yet because of the rate limit
+    pending: Option<Track>,
+    last_shown: Option<Instant>,
+    desktop: D,
+}
+
+impl<D: ShowNotification> NotificationThread<D> {
+    fn run(mut self) {
+        loop {
+            let interval = Duration::from_secs(self.settings.lock().unwrap().min_interval_s as u64);
+            let wait = self
+                .last_shown
+                .map(|last| interval.saturating_sub(last.elapsed()))
+                .unwrap_or_default();
+            let message = match self.pending {
+                Some(_) => self.receiver.recv_timeout(wait),
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match message {
+                Ok(NotifierMessage::Track(track)) => self.pending = Some(track),
+                Ok(NotifierMessage::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+            let limited = matches!(self.last_shown, Some(last) if last.elapsed() < interval);
+            if !limited {
+                match self.pending.take() {
+                    Some(track) if !track.is_finished() => self.notify(track),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn notify(&mut self, track: Track) {
+        let settings = *self.settings.lock().unwrap();
+        if !settings.enabled || (self.focused.load(Acquire) && !settings.when_focused) {
+            return;
+        }
+        let body = match track.duration_us {
+            Some(duration_us) => format!(
+                "Duration {}",
+                format::format_timestamp((duration_us / TIME_BASE) as i64)
+            ),
+            None => String::new(),
+        };
+        self.desktop.show(&track.title, &body);
+        self.last_shown = Some(Instant::now());
+    }
+}
+
+/// Freedesktop notifications over the session bus
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct Desktop {
+    connection: Option<dbus::blocking::Connection>,
+    /// Id of the last notification, replaced by the next one
+    id: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl ShowNotification for Desktop {
+    fn show(&mut self, summary: &str, body: &str) {
+        if self.connection.is_none() {
+            match dbus::blocking::Connection::new_session() {
+                Ok(connection) => self.connection = Some(connection),
+                Err(err) => {
+                    warn!("Failed to connect to the session bus: {}", err);
+                    return;
+                }
+            }
+        }
+        let proxy = self.connection.as_ref().unwrap().with_proxy(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            Duration::from_secs(1),
+        );
+        let res: Result<(u32,), _> = proxy.method_call(
+            "org.freedesktop.Notifications",
+            "Notify",
+            (
+                "Leierkasten",
+                self.id,
+                "",
+                summary,
+                body,
+                Vec::<String>::new(),
+                dbus::arg::PropMap::new(),
+                5000i32,
+            ),
+        );
+        match res {
+            Ok((id,)) => self.id = id,
+            Err(err) => warn!("Failed to show notification: {}", err),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+#[derive(Default)]
+struct Desktop;
+
+#[cfg(not(target_os = "linux"))]
+impl ShowNotification for Desktop {
+    fn show(&mut self, summary: &str, _body: &str) {
+        info!("Now playing: {}", summary);
+    }
+}
@@ -0,0 +1,74 @@
+//! Time math of the playing resource, shared by the gui, MPRIS, the control API and metrics
+
+use crate::audio_client::{SAMPLE_RATE, TIME_BASE};
+use crate::audio_socket::StreamStartMessage;
+
+pub fn samples_to_us(samples: u64) -> u64 {
+    samples * TIME_BASE / SAMPLE_RATE
+}
+
+pub fn us_to_samples(us: u64) -> u64 {
+    us * SAMPLE_RATE / TIME_BASE
+}
+
+/// Timing of a resource as it is played. Positions are relative to `start_timestamp_us`, like
+/// the player timestamp, so resources starting mid-file begin at zero
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResourceTime {
+    /// Position of the first sample received, later than zero when joining a running resource
+    pub offset_us: u64,
+    /// How long the resource plays, the shorter of end timestamp and duration limit. `None`
+    /// for open ended resources
+    pub duration_us: Option<u64>,
+}
+
+impl ResourceTime {
+    pub fn new(item: &StreamStartMessage) -> Self {
+        let until_end = item
+            .end_timestamp_us
+            .map(|end| end.saturating_sub(item.start_timestamp_us));
+        let duration_us = match (until_end, item.duration_us) {
+            (Some(until_end), Some(limit)) => Some(until_end.min(limit)),
+            (until_end, limit) => until_end.or(limit),
+        };
+        ResourceTime {
+            offset_us: samples_to_us(item.offset_samples),
+            duration_us,
+        }
+    }
+
+    /// Position at the player timestamp, kept within the played part of the resource
+    pub fn position_us(&self, timestamp: u64) -> u64 {
+        let position = samples_to_us(timestamp).max(self.offset_us);
+        match self.duration_us {
+            Some(duration) => position.min(duration),
+            None => position,
+        }
+    }
+
+    /// Played fraction between 0 and 1, `None` for open ended resources
+    pub fn progress(&self, timestamp: u64) -> Option<f32> {
+        let duration = self.duration_us?;
+        if duration == 0 {
+            return Some(1.0);
+        }
+        Some(self.position_us(timestamp) as f32 / duration as f32)
+    }
+
+    /// Player timestamp to seek to for `position_us`
+    pub fn timestamp_at(&self, position_us: u64) -> u64 {
+        let position_us = match self.duration_us {
+            Some(duration) => position_us.min(duration),
+            None => position_us,
+        };
+        us_to_samples(position_us.max(self.offset_us))
+    }
+}
+
+/// Position in `item` at the player timestamp, the plain timestamp without a resource
+pub fn position_us(item: Option<&StreamStartMessage>, timestamp: u64) -> u64 {
+    match item {
+        Some(item) => ResourceTime::new(item).position_us(timestamp),
+        None => samples_to_us(timestamp),
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use audiopus::SampleRate;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use ogg::{OggReadError, PacketReader};
+
+use crate::audio_client::{SAMPLES_PER_FRAME, SAMPLE_RATE};
+
+const VENDOR: &str = "leierkasten-client";
+
+fn opus_head() -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // Version
+    head.push(2); // Channels
+    head.extend_from_slice(&0u16.to_le_bytes()); // Pre-skip
+    head.extend_from_slice(&(SAMPLE_RATE as u32).to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // Output gain
+    head.push(0); // Channel mapping family
+    head
+}
+
+fn opus_tags(title: &str) -> Vec<u8> {
+    let comment = format!("TITLE={}", title);
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    tags.extend_from_slice(VENDOR.as_bytes());
+    tags.extend_from_slice(&1u32.to_le_bytes());
+    tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+    tags.extend_from_slice(comment.as_bytes());
+    tags
+}
+
+/// Writes raw Opus packets into an Ogg Opus container without re-encoding
+pub struct OggOpusWriter<W: Write> {
+    writer: PacketWriter<W>,
+    serial: u32,
+    granule: u64,
+    /// The last packet is held back so it can be marked as the end of the stream
+    pending: Option<Vec<u8>>,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    pub fn new(inner: W, title: &str) -> io::Result<Self> {
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut writer = PacketWriter::new(inner);
+        writer.write_packet(
+            opus_head().into_boxed_slice(),
+            serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+        writer.write_packet(
+            opus_tags(title).into_boxed_slice(),
+            serial,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+        Ok(OggOpusWriter {
+            writer,
+            serial,
+            granule: 0,
+            pending: None,
+        })
+    }
+
+    fn write_pending(&mut self, end: PacketWriteEndInfo) -> io::Result<()> {
+        if let Some(packet) = self.pending.take() {
+            self.granule += SAMPLES_PER_FRAME;
+            self.writer
+                .write_packet(packet.into_boxed_slice(), self.serial, end, self.granule)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_packet(&mut self, packet: Vec<u8>) -> io::Result<()> {
+        self.write_pending(PacketWriteEndInfo::NormalPacket)?;
+        self.pending = Some(packet);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_pending(PacketWriteEndInfo::EndStream)?;
+        self.writer.inner_mut().flush()
+    }
+}
+
+fn read_error(err: OggReadError) -> io::Error {
+    match err {
+        OggReadError::ReadError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Extracts the `TITLE` comment of an OpusTags packet
+fn parse_title(tags: &[u8]) -> Option<String> {
+    if !tags.starts_with(b"OpusTags") {
+        return None;
+    }
+    let vendor_len = read_u32(tags, 8)? as usize;
+    let mut offset = 12 + vendor_len;
+    let count = read_u32(tags, offset)?;
+    offset += 4;
+    for _ in 0..count {
+        let len = read_u32(tags, offset)? as usize;
+        offset += 4;
+        let comment = tags.get(offset..offset + len)?;
+        offset += len;
+        let comment = String::from_utf8_lossy(comment);
+        let mut split = comment.splitn(2, '=');
+        if let (Some(key), Some(value)) = (split.next(), split.next()) {
+            if key.eq_ignore_ascii_case("TITLE") {
+                return Some(value.to_owned());
+            }
+        }
+    }
+    None
+}
+
+/// Reads the Opus packets of a stereo Ogg Opus stream
+pub struct OggOpusReader<R: Read + Seek> {
+    reader: PacketReader<R>,
+    serial: u32,
+    pre_skip: u64,
+    title: Option<String>,
+    /// Length in samples, excluding the pre-skip
+    duration: u64,
+    /// Packets read ahead while seeking
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl<R: Read + Seek> OggOpusReader<R> {
+    pub fn new(inner: R) -> io::Result<Self> {
+        let mut reader = PacketReader::new(inner);
+        let head = reader.read_packet_expected().map_err(read_error)?;
+        if !head.data.starts_with(b"OpusHead") || head.data.len() < 19 {
+            return Err(invalid_data("Not an Ogg Opus stream"));
+        }
+        if head.data[9] != 2 {
+            return Err(invalid_data("Only stereo streams are supported"));
+        }
+        let serial = head.stream_serial();
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        let tags = reader.read_packet_expected().map_err(read_error)?;
+        let title = parse_title(&tags.data);
+
+        // Scan the stream once to find its length
+        let mut end = 0;
+        while let Some(packet) = reader.read_packet().map_err(read_error)? {
+            if packet.stream_serial() == serial && packet.last_in_page() {
+                end = packet.absgp_page();
+            }
+        }
+
+        let mut reader = OggOpusReader {
+            reader,
+            serial,
+            pre_skip,
+            title,
+            duration: end.saturating_sub(pre_skip),
+            pending: VecDeque::new(),
+        };
+        reader.seek(0)?;
+        Ok(reader)
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    pub fn pre_skip(&self) -> u64 {
+        self.pre_skip
+    }
+
+    /// Seeks to the page containing `sample`, excluding the pre-skip. Returns the granule
+    /// position of the first sample of the next packet, including the pre-skip, which is at or
+    /// before `sample`
+    pub fn seek(&mut self, sample: u64) -> io::Result<u64> {
+        self.pending.clear();
+        if sample == 0 {
+            self.reader.seek_bytes(SeekFrom::Start(0))?;
+            // Skip the headers
+            self.reader.read_packet_expected().map_err(read_error)?;
+            self.reader.read_packet_expected().map_err(read_error)?;
+            return Ok(0);
+        }
+        self.reader
+            .seek_absgp(Some(self.serial), sample + self.pre_skip)
+            .map_err(read_error)?;
+        // The granule position of a page is the end of its last packet, read the whole page to
+        // find where it starts
+        let mut samples = 0;
+        loop {
+            let packet = match self.reader.read_packet().map_err(read_error)? {
+                Some(packet) if packet.stream_serial() == self.serial => packet,
+                Some(_) => continue,
+                None => return Ok(sample + self.pre_skip),
+            };
+            samples += audiopus::packet::nb_samples(packet.data.as_slice(), SampleRate::Hz48000)
+                .map_err(|_| invalid_data("Invalid Opus packet"))? as u64;
+            let end = packet.last_in_page().then(|| packet.absgp_page());
+            self.pending.push_back(packet.data);
+            if let Some(end) = end {
+                return Ok(end.saturating_sub(samples));
+            }
+        }
+    }
+
+    /// Returns the next audio packet or `None` at the end of the stream
+    pub fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(data) = self.pending.pop_front() {
+            return Ok(Some(data));
+        }
+        loop {
+            match self.reader.read_packet().map_err(read_error)? {
+                None => return Ok(None),
+                Some(packet) if packet.stream_serial() == self.serial => {
+                    return Ok(Some(packet.data))
+                }
+                Some(_) => (),
+            }
+        }
+    }
+}
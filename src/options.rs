@@ -0,0 +1,80 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+/// Command line options
+pub struct Options {
+    /// Play an Ogg Opus file instead of connecting to the server
+    pub play: Option<PathBuf>,
+    /// Discard the audio instead of playing it, e.g. on machines without an audio device
+    pub null_output: bool,
+    /// Record the websocket traffic into a session file
+    pub record_session: Option<PathBuf>,
+    /// Replay a recorded session file instead of connecting to the server
+    pub replay_session: Option<PathBuf>,
+    /// Serve Prometheus metrics on this address, e.g. `127.0.0.1:9185`
+    pub metrics: Option<SocketAddr>,
+    /// Serve the control API on this port
+    pub control_port: Option<u16>,
+    /// Interface for the control API, only localhost by default
+    pub control_bind: IpAddr,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            play: None,
+            null_output: false,
+            record_session: None,
+            replay_session: None,
+            metrics: None,
+            control_port: None,
+            control_bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+impl Options {
+    pub fn parse() -> Self {
+        let mut options = Options::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--play" => match args.next() {
+                    Some(path) => options.play = Some(path.into()),
+                    None => warn!("Missing file for --play"),
+                },
+                "--null-output" => options.null_output = true,
+                "--record-session" => match args.next() {
+                    Some(path) => options.record_session = Some(path.into()),
+                    None => warn!("Missing file for --record-session"),
+                },
+                "--replay-session" => match args.next() {
+                    Some(path) => options.replay_session = Some(path.into()),
+                    None => warn!("Missing file for --replay-session"),
+                },
+                "--metrics" => match args.next().map(|address| address.parse()) {
+                    Some(Ok(address)) => options.metrics = Some(address),
+                    Some(Err(err)) => warn!("Invalid address for --metrics: {}", err),
+                    None => warn!("Missing address for --metrics"),
+                },
+                "--control-port" => match args.next().map(|port| port.parse()) {
+                    Some(Ok(port)) => options.control_port = Some(port),
+                    Some(Err(err)) => warn!("Invalid port for --control-port: {}", err),
+                    None => warn!("Missing port for --control-port"),
+                },
+                "--control-bind" => match args.next().map(|address| address.parse()) {
+                    Some(Ok(address)) => options.control_bind = address,
+                    Some(Err(err)) => warn!("Invalid address for --control-bind: {}", err),
+                    None => warn!("Missing address for --control-bind"),
+                },
+                _ => warn!("Unknown argument {}", arg),
+            }
+        }
+        options
+    }
+
+    pub fn control_address(&self) -> Option<SocketAddr> {
+        self.control_port
+            .map(|port| SocketAddr::new(self.control_bind, port))
+    }
+}
@@ -0,0 +1,184 @@
+//! Output sinks without an audio device, for running headless and in tests
+
+use std::io;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::audio_client::SAMPLE_RATE;
+use crate::audio_stream::{OutputSink, SourceReader};
+use crate::statistics::Statistics;
+
+/// Frames pulled at once, similar to the buffer size of an audio device
+const BLOCK_FRAMES: u64 = 480;
+
+#[derive(Clone, Copy)]
+pub enum Pace {
+    /// As fast as a device playing at the stream sample rate
+    RealTime,
+    /// Faster than real time by the given factor
+    #[cfg(test)]
+    Accelerated(f32),
+    /// As fast as the source provides data
+    #[cfg(test)]
+    Unlimited,
+}
+
+impl Pace {
+    fn elapsed(self, frames: u64) -> Option<Duration> {
+        let speed = match self {
+            Pace::RealTime => 1.0,
+            #[cfg(test)]
+            Pace::Accelerated(speed) => speed as f64,
+            #[cfg(test)]
+            Pace::Unlimited => return None,
+        };
+        Some(Duration::from_secs_f64(
+            frames as f64 / SAMPLE_RATE as f64 / speed,
+        ))
+    }
+}
+
+/// Progress of a pacing thread, shared with its sink
+#[derive(Default)]
+struct Progress {
+    running: AtomicBool,
+    frames: AtomicU64,
+}
+
+/// Pulls blocks from `reader` until `limit` frames were written or the sink is stopped
+fn spawn_paced<W>(
+    mut reader: SourceReader,
+    pace: Pace,
+    limit: Option<u64>,
+    progress: Arc<Progress>,
+    mut write: W,
+) -> JoinHandle<io::Result<()>>
+where
+    W: FnMut(&[f32]) -> io::Result<()> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let mut buffer = vec![0.0; BLOCK_FRAMES as usize * 2];
+        let mut written = 0;
+        while progress.running.load(Acquire) {
+            let frames = match limit {
+                Some(limit) if written >= limit => break,
+                Some(limit) => BLOCK_FRAMES.min(limit - written),
+                None => BLOCK_FRAMES,
+            };
+            let block = &mut buffer[..frames as usize * 2];
+            reader.fill(block);
+            write(block)?;
+            written += frames;
+            progress.frames.store(written, Release);
+
+            if let Some(elapsed) = pace.elapsed(written) {
+                if let Some(wait) = elapsed.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Shared parts of `NullSink` and the tests' `CaptureSink`
+pub(crate) struct PacedSink {
+    pace: Pace,
+    progress: Arc<Progress>,
+    statistics: Arc<Statistics>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl PacedSink {
+    pub(crate) fn new(pace: Pace) -> Self {
+        PacedSink {
+            pace,
+            progress: Default::default(),
+            statistics: Default::default(),
+            thread: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn underruns(&self) -> u64 {
+        self.statistics.underruns()
+    }
+
+    pub(crate) fn start<W>(&mut self, reader: SourceReader, limit: Option<u64>, write: W)
+    where
+        W: FnMut(&[f32]) -> io::Result<()> + Send + 'static,
+    {
+        self.stop();
+        self.progress.running.store(true, Release);
+        self.progress.frames.store(0, Release);
+        self.statistics = reader.statistics().clone();
+        self.thread = Some(spawn_paced(
+            reader,
+            self.pace,
+            limit,
+            self.progress.clone(),
+            write,
+        ));
+    }
+
+    pub(crate) fn wait(&mut self) -> io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("Output thread panicked"))),
+            None => Ok(()),
+        }
+    }
+
+    fn stop(&mut self) {
+        self.progress.running.store(false, Release);
+        if let Err(err) = self.wait() {
+            warn!("Output failed: {}", err);
+        }
+    }
+}
+
+impl Drop for PacedSink {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Discards the audio, pulling it at the given pace
+pub struct NullSink {
+    sink: PacedSink,
+}
+
+impl NullSink {
+    pub fn new(pace: Pace) -> Self {
+        NullSink {
+            sink: PacedSink::new(pace),
+        }
+    }
+
+    /// Frames pulled since the sink was started
+    #[cfg(test)]
+    pub fn frames(&self) -> u64 {
+        self.sink.progress.frames.load(Acquire)
+    }
+
+    #[cfg(test)]
+    pub fn underruns(&self) -> u64 {
+        self.sink.underruns()
+    }
+
+    #[cfg(test)]
+    pub fn stop(&mut self) {
+        self.sink.stop();
+    }
+}
+
+impl OutputSink for NullSink {
+    fn start(&mut self, reader: SourceReader) {
+        self.sink.start(reader, None, |_| Ok(()));
+    }
+}
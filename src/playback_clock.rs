@@ -0,0 +1,95 @@
+//! Position of the audio that is actually heard, fed by the output callback
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::audio_client::SAMPLE_RATE;
+
+/// Segments kept for the output latency, more are dropped from the front
+const MAX_SEGMENTS: usize = 256;
+
+/// Output frames played from a resource without a jump in its position
+#[derive(Clone, Copy)]
+struct Segment {
+    /// When the first frame is heard
+    heard_at: Instant,
+    /// Positions in the resource at the start and end, equal while paused
+    start: u64,
+    end: u64,
+    frames: u64,
+}
+
+impl Segment {
+    fn position(&self, now: Instant) -> u64 {
+        let elapsed = now.duration_since(self.heard_at).as_secs_f64() * SAMPLE_RATE as f64;
+        let elapsed = elapsed as u64;
+        if elapsed >= self.frames {
+            // Nothing was written after this segment, the clock stops
+            self.end
+        } else {
+            self.start + (self.end - self.start) * elapsed / self.frames
+        }
+    }
+}
+
+pub struct PlaybackClock {
+    /// Segments written to the output, ordered by `heard_at`
+    segments: Mutex<VecDeque<Segment>>,
+}
+
+impl Default for PlaybackClock {
+    fn default() -> Self {
+        // Allocated once, the output callback only reuses it
+        PlaybackClock {
+            segments: Mutex::new(VecDeque::with_capacity(MAX_SEGMENTS)),
+        }
+    }
+}
+
+impl PlaybackClock {
+    /// Queues `frames` output frames covering the resource from `start` to `end`, heard from
+    /// `heard_at`. `now` is the time of the output callback. Never blocks, the segment is
+    /// dropped while the gui reads the position
+    pub fn push(&self, now: Instant, heard_at: Instant, start: u64, end: u64, frames: u64) {
+        if frames == 0 {
+            return;
+        }
+        let mut segments = match self.segments.try_lock() {
+            Ok(segments) => segments,
+            Err(_) => return,
+        };
+        // Segments that were completely heard are no longer needed
+        while segments.len() > 1 && segments[1].heard_at <= now {
+            segments.pop_front();
+        }
+        if segments.len() >= MAX_SEGMENTS {
+            segments.pop_front();
+        }
+        segments.push_back(Segment {
+            heard_at,
+            start,
+            end: end.max(start),
+            frames,
+        });
+    }
+
+    /// Position in the resource heard at `now`, `None` before any output
+    pub fn position(&self, now: Instant) -> Option<u64> {
+        let segments = self.segments.lock().unwrap();
+        let segment = segments
+            .iter()
+            .rev()
+            .find(|segment| segment.heard_at <= now);
+        match segment {
+            Some(segment) => Some(segment.position(now)),
+            // Written but not heard yet
+            None => segments.front().map(|segment| segment.start),
+        }
+    }
+
+    /// Forgets all output, e.g. after disconnecting
+    pub fn reset(&self) {
+        self.segments.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,301 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio_client::SAMPLE_RATE;
+use crate::ogg_opus::OggOpusWriter;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RecordFormat {
+    /// The original Opus packets in an Ogg container
+    OggOpus,
+    /// Decoded samples as 32 bit float WAV
+    Wav,
+}
+
+impl RecordFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::OggOpus => "opus",
+            RecordFormat::Wav => "wav",
+        }
+    }
+
+    fn to_tap(self) -> u8 {
+        match self {
+            RecordFormat::OggOpus => TAP_OPUS,
+            RecordFormat::Wav => TAP_PCM,
+        }
+    }
+}
+
+const TAP_OFF: u8 = 0;
+const TAP_OPUS: u8 = 1;
+const TAP_PCM: u8 = 2;
+
+enum RecorderMessage {
+    Start(PathBuf, RecordFormat),
+    Stop,
+    Shutdown,
+    NewResource(String),
+    Opus(Vec<u8>),
+    Pcm(Vec<f32>),
+}
+
+/// Audio thread side of the recorder, only copies data while a recording is running
+pub struct RecorderTap {
+    sender: Sender<RecorderMessage>,
+    tap: Arc<AtomicU8>,
+}
+
+impl RecorderTap {
+    fn send(&self, message: RecorderMessage) {
+        let _ = self.sender.send(message);
+    }
+
+    pub fn new_resource(&self, name: &str) {
+        self.send(RecorderMessage::NewResource(name.to_owned()));
+    }
+
+    pub fn opus(&self, data: &[u8]) {
+        if self.tap.load(Acquire) == TAP_OPUS {
+            self.send(RecorderMessage::Opus(data.to_vec()));
+        }
+    }
+
+    pub fn pcm(&self, samples: &[f32]) {
+        if self.tap.load(Acquire) == TAP_PCM {
+            self.send(RecorderMessage::Pcm(samples.to_vec()));
+        }
+    }
+}
+
+/// Gui side of the recorder
+pub struct Recorder {
+    sender: Sender<RecorderMessage>,
+    tap: Arc<AtomicU8>,
+    current_file: Arc<Mutex<Option<PathBuf>>>,
+    thread: JoinHandle<()>,
+}
+
+impl Recorder {
+    /// Spawns the thread writing the files
+    pub fn spawn() -> (Recorder, RecorderTap) {
+        let (sender, receiver) = channel();
+        let tap = Arc::new(AtomicU8::new(TAP_OFF));
+        let current_file = Arc::new(Mutex::new(None));
+        let writer = RecordingThread {
+            receiver,
+            tap: tap.clone(),
+            current_file: current_file.clone(),
+            session: None,
+            resource: None,
+            track: None,
+        };
+        let thread = std::thread::spawn(move || writer.run());
+        let recorder = Recorder {
+            sender: sender.clone(),
+            tap: tap.clone(),
+            current_file,
+            thread,
+        };
+        (recorder, RecorderTap { sender, tap })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.tap.load(Acquire) != TAP_OFF
+    }
+
+    pub fn current_file(&self) -> Option<PathBuf> {
+        self.current_file.lock().unwrap().clone()
+    }
+
+    pub fn start(&self, directory: PathBuf, format: RecordFormat) {
+        let _ = self.sender.send(RecorderMessage::Start(directory, format));
+        self.tap.store(format.to_tap(), Release);
+    }
+
+    pub fn stop(&self) {
+        self.tap.store(TAP_OFF, Release);
+        let _ = self.sender.send(RecorderMessage::Stop);
+    }
+
+    /// Finishes the current file and waits for the recording thread to exit
+    pub fn shutdown(self) {
+        self.tap.store(TAP_OFF, Release);
+        let _ = self.sender.send(RecorderMessage::Shutdown);
+        let _ = self.thread.join();
+    }
+}
+
+enum TrackWriter {
+    OggOpus(OggOpusWriter<BufWriter<File>>),
+    Wav(hound::WavWriter<BufWriter<File>>),
+}
+
+impl TrackWriter {
+    fn create(path: &Path, format: RecordFormat, title: &str) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            RecordFormat::OggOpus => TrackWriter::OggOpus(OggOpusWriter::new(file, title)?),
+            RecordFormat::Wav => {
+                TrackWriter::Wav(hound::WavWriter::new(file, wav_spec()).map_err(hound_error)?)
+            }
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            TrackWriter::OggOpus(writer) => writer.finish(),
+            TrackWriter::Wav(writer) => writer.finalize().map_err(hound_error),
+        }
+    }
+}
+
+/// Stereo 32 bit float at the stream sample rate
+pub fn wav_spec() -> hound::WavSpec {
+    hound::WavSpec {
+        channels: 2,
+        sample_rate: SAMPLE_RATE as u32,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    }
+}
+
+pub fn hound_error(err: hound::Error) -> io::Error {
+    match err {
+        hound::Error::IoError(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// Replaces characters that are not allowed in file names
+fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(120)
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "Recording".into()
+    } else {
+        name.into()
+    }
+}
+
+fn unique_path(directory: &Path, name: &str, extension: &str) -> PathBuf {
+    let name = sanitize_file_name(name);
+    let mut path = directory.join(format!("{}.{}", name, extension));
+    let mut index = 2;
+    while path.exists() {
+        path = directory.join(format!("{} ({}).{}", name, index, extension));
+        index += 1;
+    }
+    path
+}
+
+struct RecordingThread {
+    receiver: Receiver<RecorderMessage>,
+    tap: Arc<AtomicU8>,
+    current_file: Arc<Mutex<Option<PathBuf>>>,
+    session: Option<(PathBuf, RecordFormat)>,
+    resource: Option<String>,
+    track: Option<TrackWriter>,
+}
+
+impl RecordingThread {
+    fn finish_track(&mut self) {
+        if let Some(track) = self.track.take() {
+            if let Err(err) = track.finish() {
+                warn!("Failed to finish recording: {}", err);
+            }
+        }
+        *self.current_file.lock().unwrap() = None;
+    }
+
+    fn start_track(&mut self) {
+        self.finish_track();
+        let (directory, format) = match self.session.clone() {
+            Some(session) => session,
+            None => return,
+        };
+        let title = self.resource.clone().unwrap_or_else(|| "Recording".into());
+        if let Err(err) = std::fs::create_dir_all(&directory) {
+            warn!("Failed to create {}: {}", directory.display(), err);
+            self.tap.store(TAP_OFF, Release);
+            self.session = None;
+            return;
+        }
+        let path = unique_path(&directory, &title, format.extension());
+        match TrackWriter::create(&path, format, &title) {
+            Ok(track) => {
+                info!("Recording to {}", path.display());
+                self.track = Some(track);
+                *self.current_file.lock().unwrap() = Some(path);
+            }
+            Err(err) => {
+                warn!("Failed to create {}: {}", path.display(), err);
+                self.tap.store(TAP_OFF, Release);
+                self.session = None;
+            }
+        }
+    }
+
+    fn write(&mut self, message: RecorderMessage) -> io::Result<()> {
+        match (message, self.track.as_mut()) {
+            (RecorderMessage::Opus(data), Some(TrackWriter::OggOpus(writer))) => {
+                writer.write_packet(data)
+            }
+            (RecorderMessage::Pcm(samples), Some(TrackWriter::Wav(writer))) => {
+                for sample in samples {
+                    writer.write_sample(sample).map_err(hound_error)?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn run(mut self) {
+        while let Ok(message) = self.receiver.recv() {
+            match message {
+                RecorderMessage::Start(directory, format) => {
+                    self.session = Some((directory, format));
+                    self.start_track();
+                }
+                RecorderMessage::Stop => {
+                    self.session = None;
+                    self.finish_track();
+                }
+                RecorderMessage::Shutdown => break,
+                RecorderMessage::NewResource(name) => {
+                    self.resource = Some(name);
+                    if self.session.is_some() {
+                        self.start_track();
+                    }
+                }
+                message => {
+                    if let Err(err) = self.write(message) {
+                        warn!("Failed to write recording, stopping: {}", err);
+                        self.tap.store(TAP_OFF, Release);
+                        self.session = None;
+                        self.finish_track();
+                    }
+                }
+            }
+        }
+        self.finish_track();
+    }
+}
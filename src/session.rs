@@ -0,0 +1,227 @@
+//! Recording and replaying the websocket traffic of a session, to reproduce problems locally
+//!
+//! A session file starts with `MAGIC`, followed by one record per message:
+//! the arrival time in microseconds since the first message (u64), the message kind
+//! (u8), the length of the payload (u32) and the payload, all little endian.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::Sender;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::audio_socket::{parse_message, AudioMessage, SocketToken, State};
+use crate::statistics::Statistics;
+use crate::token::*;
+
+const MAGIC: &[u8; 8] = b"LKSESS\x00\x01";
+
+const KIND_TEXT: u8 = 0;
+const KIND_BINARY: u8 = 1;
+
+/// Largest payload of a record, enough for a resource message with a base64 encoded thumbnail.
+/// Opus frames are only a few hundred bytes
+const MAX_RECORD_LEN: usize = 4 * 1024 * 1024;
+
+/// Appends the received messages to a session file, shared by all connections of the client
+pub struct SessionWriter {
+    writer: BufWriter<File>,
+    /// Arrival of the first message, the time before connecting isn't replayed
+    start: Option<Instant>,
+}
+
+pub type SharedSessionWriter = Arc<Mutex<SessionWriter>>;
+
+impl SessionWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(SessionWriter {
+            writer,
+            start: None,
+        })
+    }
+
+    /// Records text and binary messages, others and oversized ones are ignored
+    pub fn write(&mut self, message: &Message) -> io::Result<()> {
+        let (kind, data) = match message {
+            Message::Text(text) => (KIND_TEXT, text.as_bytes()),
+            Message::Binary(data) => (KIND_BINARY, data.as_slice()),
+            _ => return Ok(()),
+        };
+        // The reader refuses larger records, the rest of the session stays readable
+        if data.len() > MAX_RECORD_LEN {
+            warn!("Not recording a message of {} bytes", data.len());
+            return Ok(());
+        }
+        let time_us = self
+            .start
+            .get_or_insert_with(Instant::now)
+            .elapsed()
+            .as_micros() as u64;
+        self.writer.write_all(&time_us.to_le_bytes())?;
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        // Flush every message so the file is complete if the client crashes
+        self.writer.flush()
+    }
+}
+
+/// A single recorded message
+pub struct SessionRecord {
+    /// Arrival time since the first message
+    pub time: Duration,
+    pub message: Message,
+}
+
+pub struct SessionReader<R: Read> {
+    reader: R,
+}
+
+impl SessionReader<BufReader<File>> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        SessionReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> SessionReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a session file",
+            ));
+        }
+        Ok(SessionReader { reader })
+    }
+
+    /// Returns the next record or `None` at the end of the file
+    pub fn next_record(&mut self) -> io::Result<Option<SessionRecord>> {
+        let mut header = [0; 13];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            // A truncated record at the end is expected if the client crashed while writing
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let mut time_us = [0; 8];
+        time_us.copy_from_slice(&header[..8]);
+        let kind = header[8];
+        let mut len = [0; 4];
+        len.copy_from_slice(&header[9..]);
+
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Record too large",
+            ));
+        }
+        let mut data = vec![0; len];
+        match self.reader.read_exact(&mut data) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let message = match kind {
+            KIND_TEXT => Message::Text(
+                String::from_utf8(data)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            ),
+            KIND_BINARY => Message::Binary(data),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Unknown message kind",
+                ))
+            }
+        };
+        Ok(Some(SessionRecord {
+            time: Duration::from_micros(u64::from_le_bytes(time_us)),
+            message,
+        }))
+    }
+}
+
+/// Feeds a recorded session into the output channel with the original timing, replacing an
+/// `AudioSocket`
+pub struct SessionReplay {
+    path: PathBuf,
+    token: SocketToken,
+    updates: Arc<Mutex<State>>,
+    output: Sender<AudioMessage>,
+    statistics: Arc<Statistics>,
+}
+
+impl SessionReplay {
+    pub fn new(
+        path: PathBuf,
+        token: SocketToken,
+        updates: Arc<Mutex<State>>,
+        output: Sender<AudioMessage>,
+        statistics: Arc<Statistics>,
+    ) -> Self {
+        SessionReplay {
+            path,
+            token,
+            updates,
+            output,
+            statistics,
+        }
+    }
+
+    pub async fn run(mut self) {
+        let token = TokenCompleter::new(self.token.clone());
+        *self.updates.lock().unwrap() = State::Connecting;
+
+        let mut reader = match SessionReader::open(&self.path) {
+            Ok(reader) => reader,
+            Err(err) => {
+                warn!("Failed to open session {}: {}", self.path.display(), err);
+                return;
+            }
+        };
+        info!("Replaying session {}", self.path.display());
+        *self.updates.lock().unwrap() = State::Connected;
+
+        let start = tokio::time::Instant::now();
+        while !token.token().is_canceled() {
+            let record = match reader.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    info!("End of session");
+                    break;
+                }
+                Err(err) => {
+                    warn!("Failed to read session: {}", err);
+                    break;
+                }
+            };
+            // Wait in small steps to react to cancellation during long gaps
+            let deadline = start + record.time;
+            while !token.token().is_canceled() && tokio::time::Instant::now() < deadline {
+                let step = tokio::time::Instant::now() + Duration::from_millis(20);
+                tokio::time::delay_until(deadline.min(step)).await;
+            }
+            if token.token().is_canceled() {
+                break;
+            }
+            if let Message::Binary(data) = &record.message {
+                self.statistics.add_packet(data.len());
+            }
+            if let Some(message) = parse_message(record.message) {
+                if self.output.send(message).await.is_err() {
+                    warn!("AudioMessage receiver disconnected");
+                    break;
+                }
+            }
+        }
+        *self.updates.lock().unwrap() = State::Disconnecting;
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio_client::{ResumeMode, Transition};
+use crate::effects::{default_effects, EffectConfig, EffectKind, MAX_EFFECTS};
+use crate::equalizer::{EqualizerPreset, EqualizerSettings, MAX_BANDS};
+use crate::fonts::FontSettings;
+use crate::notifications::NotificationSettings;
+use crate::recorder::RecordFormat;
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingSettings {
+    pub format: RecordFormat,
+    pub directory: PathBuf,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        let directory = dirs::audio_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_default()
+            .join("Leierkasten");
+        RecordingSettings {
+            format: RecordFormat::OggOpus,
+            directory,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct PauseSettings {
+    /// Used when resuming from the control API or media keys
+    pub resume_mode: ResumeMode,
+    /// Seconds of audio kept while paused
+    pub max_buffer_s: u32,
+    /// Seconds of played audio kept for rewinding
+    pub rewind_s: u32,
+}
+
+impl Default for PauseSettings {
+    fn default() -> Self {
+        PauseSettings {
+            resume_mode: ResumeMode::TimeShift,
+            max_buffer_s: 60,
+            rewind_s: 300,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MiniPlayerSettings {
+    pub enabled: bool,
+    pub always_on_top: bool,
+}
+
+impl Default for MiniPlayerSettings {
+    fn default() -> Self {
+        MiniPlayerSettings {
+            enabled: false,
+            always_on_top: true,
+        }
+    }
+}
+
+/// Windows that are open, their placement is stored by imgui
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub player: bool,
+    pub equalizer: bool,
+    pub effects: bool,
+    pub settings: bool,
+    pub diagnostics: bool,
+    pub history: bool,
+    pub theme: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings {
+            player: true,
+            equalizer: false,
+            effects: false,
+            settings: false,
+            diagnostics: false,
+            history: false,
+            theme: false,
+        }
+    }
+}
+
+/// Key names like `Space`, `M` or `Ctrl+D` for the keyboard shortcuts
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShortcutSettings {
+    pub connect: String,
+    pub pause: String,
+    pub volume_up: String,
+    pub volume_down: String,
+    pub mute: String,
+    pub diagnostics: String,
+    pub help: String,
+}
+
+impl Default for ShortcutSettings {
+    fn default() -> Self {
+        ShortcutSettings {
+            connect: "Space".into(),
+            pause: "P".into(),
+            volume_up: "Up".into(),
+            volume_down: "Down".into(),
+            mute: "M".into(),
+            diagnostics: "Ctrl+D".into(),
+            help: "F1".into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub effects: Vec<EffectConfig>,
+    pub equalizer: EqualizerSettings,
+    pub equalizer_presets: Vec<EqualizerPreset>,
+    /// Master volume between 0 and 1
+    pub volume: f32,
+    pub transition: Transition,
+    pub recording: RecordingSettings,
+    pub pause: PauseSettings,
+    pub shortcuts: ShortcutSettings,
+    pub windows: WindowSettings,
+    /// Name of the selected theme
+    pub theme: String,
+    pub font: FontSettings,
+    /// UI scale on top of the DPI scale factor
+    pub zoom: f32,
+    pub mini_player: MiniPlayerSettings,
+    pub notifications: NotificationSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            effects: default_effects(),
+            equalizer: Default::default(),
+            equalizer_presets: Vec::new(),
+            volume: 1.0,
+            transition: Default::default(),
+            recording: Default::default(),
+            pause: Default::default(),
+            shortcuts: Default::default(),
+            windows: Default::default(),
+            theme: "Dark".into(),
+            font: Default::default(),
+            zoom: 1.0,
+            mini_player: Default::default(),
+            notifications: Default::default(),
+        }
+    }
+}
+
+/// Supported UI zoom
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 3.0;
+
+/// Zoom within the supported range, imgui aborts on a scale of zero or less
+pub fn clamp_zoom(zoom: f32) -> f32 {
+    if zoom.is_finite() {
+        zoom.clamp(MIN_ZOOM, MAX_ZOOM)
+    } else {
+        1.0
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("leierkasten-client"))
+}
+
+/// Window layout saved by imgui
+pub fn layout_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("imgui.ini"))
+}
+
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("settings.json"))
+}
+
+impl Settings {
+    /// Loads the settings file, falling back to defaults if it is missing or invalid
+    pub fn load() -> Self {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return Settings::default(),
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => return Settings::default(),
+        };
+        match serde_json::from_reader(BufReader::new(file)) {
+            Ok(settings) => Settings::validated(settings),
+            Err(err) => {
+                warn!("Failed to parse {}: {}", path.display(), err);
+                Settings::default()
+            }
+        }
+    }
+
+    /// Replaces values from a hand edited file that would break the gui
+    pub fn validated(mut self) -> Self {
+        self.zoom = clamp_zoom(self.zoom);
+        self.font.size = self.font.clamped_size();
+        // The equalizer window edits a single equalizer
+        let mut equalizer = false;
+        self.effects.retain(|config| {
+            let duplicate = equalizer && config.kind == EffectKind::Equalizer;
+            equalizer |= config.kind == EffectKind::Equalizer;
+            !duplicate
+        });
+        self.effects.truncate(MAX_EFFECTS);
+        self.equalizer.bands.truncate(MAX_BANDS);
+        for preset in self.equalizer_presets.iter_mut() {
+            preset.bands.truncate(MAX_BANDS);
+        }
+        self
+    }
+
+    pub fn save(&self) {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => {
+                warn!("No config directory, not saving settings");
+                return;
+            }
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(err) = std::fs::create_dir_all(dir) {
+                warn!("Failed to create {}: {}", dir.display(), err);
+                return;
+            }
+        }
+        let res = File::create(&path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(BufWriter::new(file), self));
+        if let Err(err) = res {
+            warn!("Failed to save {}: {}", path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release};
+use std::time::{Duration, Instant};
+
+use crate::audio_client::{SAMPLES_PER_FRAME, SAMPLE_RATE, TIME_BASE};
+
+const NONE: u64 = u64::MAX;
+
+/// Playback counters, updated by the socket and the audio thread without locking
+pub struct Statistics {
+    /// Reference point for the timestamps stored below
+    epoch: Instant,
+    underruns: AtomicU64,
+    decode_errors: AtomicU64,
+    connections: AtomicU64,
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    /// Arrival time of the last packet in microseconds since `epoch`
+    last_arrival_us: AtomicU64,
+    /// Interarrival jitter in microseconds, as defined by RFC 3550
+    jitter_us: AtomicU64,
+    buffer_min: AtomicU64,
+    buffer_max: AtomicU64,
+    /// Start of the current buffering period in microseconds since `epoch`
+    buffering_since_us: AtomicU64,
+    buffering_us: AtomicU64,
+}
+
+/// Values of the counters at one point in time
+#[derive(Clone, Copy, Default)]
+pub struct Counters {
+    pub underruns: u64,
+    pub decode_errors: u64,
+    pub reconnects: u64,
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Counters {
+    /// Counts since `baseline` was taken
+    pub fn since(&self, baseline: &Counters) -> Counters {
+        Counters {
+            underruns: self.underruns.saturating_sub(baseline.underruns),
+            decode_errors: self.decode_errors.saturating_sub(baseline.decode_errors),
+            reconnects: self.reconnects.saturating_sub(baseline.reconnects),
+            packets: self.packets.saturating_sub(baseline.packets),
+            bytes: self.bytes.saturating_sub(baseline.bytes),
+        }
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Statistics {
+            epoch: Instant::now(),
+            underruns: Default::default(),
+            decode_errors: Default::default(),
+            connections: Default::default(),
+            packets: Default::default(),
+            bytes: Default::default(),
+            last_arrival_us: AtomicU64::new(NONE),
+            jitter_us: Default::default(),
+            buffer_min: AtomicU64::new(NONE),
+            buffer_max: Default::default(),
+            buffering_since_us: AtomicU64::new(NONE),
+            buffering_us: Default::default(),
+        }
+    }
+}
+
+impl Statistics {
+    fn now_us(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    /// Resets jitter, buffer range and buffering time, a running buffering period is counted
+    /// from now on. The counters are exported as metrics and stay monotonic, views compare them
+    /// to a baseline instead
+    pub fn reset_measurements(&self) {
+        self.last_arrival_us.store(NONE, Release);
+        self.jitter_us.store(0, Release);
+        self.buffer_min.store(NONE, Release);
+        self.buffer_max.store(0, Release);
+        if self.buffering_since_us.load(Acquire) != NONE {
+            self.buffering_since_us.store(self.now_us(), Release);
+        }
+        self.buffering_us.store(0, Release);
+    }
+
+    pub fn add_underrun(&self) {
+        self.underruns.fetch_add(1, AcqRel);
+    }
+
+    pub fn add_decode_error(&self) {
+        self.decode_errors.fetch_add(1, AcqRel);
+    }
+
+    pub fn add_connection(&self) {
+        self.connections.fetch_add(1, AcqRel);
+    }
+
+    /// Called by the socket for every received audio packet
+    pub fn add_packet(&self, bytes: usize) {
+        self.packets.fetch_add(1, AcqRel);
+        self.bytes.fetch_add(bytes as u64, AcqRel);
+
+        let now = self.now_us();
+        let last = self.last_arrival_us.swap(now, AcqRel);
+        if last != NONE {
+            let expected = SAMPLES_PER_FRAME * TIME_BASE / SAMPLE_RATE;
+            let deviation = (now.saturating_sub(last) as i64 - expected as i64).abs() as f64;
+            let jitter = self.jitter_us.load(Acquire) as f64;
+            let jitter = jitter + (deviation - jitter) / 16.0;
+            self.jitter_us.store(jitter as u64, Release);
+        }
+    }
+
+    pub fn update_buffer(&self, buffer: usize) {
+        self.buffer_min.fetch_min(buffer as u64, AcqRel);
+        self.buffer_max.fetch_max(buffer as u64, AcqRel);
+    }
+
+    pub fn set_buffering(&self, buffering: bool) {
+        let now = self.now_us();
+        if buffering {
+            let _ = self
+                .buffering_since_us
+                .compare_exchange(NONE, now, AcqRel, Acquire);
+        } else {
+            let since = self.buffering_since_us.swap(NONE, AcqRel);
+            if since != NONE {
+                self.buffering_us
+                    .fetch_add(now.saturating_sub(since), AcqRel);
+            }
+        }
+    }
+
+    pub fn counters(&self) -> Counters {
+        Counters {
+            underruns: self.underruns(),
+            decode_errors: self.decode_errors(),
+            reconnects: self.reconnects(),
+            packets: self.packets(),
+            bytes: self.bytes(),
+        }
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Acquire)
+    }
+
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.load(Acquire)
+    }
+
+    /// Successful connections after the first one
+    pub fn reconnects(&self) -> u64 {
+        self.connections.load(Acquire).saturating_sub(1)
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Acquire)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Acquire)
+    }
+
+    pub fn jitter(&self) -> Duration {
+        Duration::from_micros(self.jitter_us.load(Acquire))
+    }
+
+    /// Smallest and largest buffer size in frames, `None` if nothing was buffered yet
+    pub fn buffer_range(&self) -> Option<(usize, usize)> {
+        match self.buffer_min.load(Acquire) {
+            NONE => None,
+            min => Some((min as usize, self.buffer_max.load(Acquire) as usize)),
+        }
+    }
+
+    /// Total time spent buffering, including a running buffering period
+    pub fn buffering_time(&self) -> Duration {
+        let mut total = self.buffering_us.load(Acquire);
+        let since = self.buffering_since_us.load(Acquire);
+        if since != NONE {
+            total += self.now_us().saturating_sub(since);
+        }
+        Duration::from_micros(total)
+    }
+}
@@ -0,0 +1,326 @@
+//! In-process websocket server emulating a leierkasten server and other helpers for tests
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use audiopus::coder::Encoder;
+use audiopus::{Application, Channels, SampleRate};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::audio_client::{SAMPLES_PER_FRAME, SAMPLE_RATE};
+use crate::audio_stream::{AudioSource, OutputSink, SourceReader};
+use crate::output::{Pace, PacedSink};
+use crate::recorder::{hound_error, wav_spec};
+
+/// A single action of the server, executed in order for every connection
+pub enum Step {
+    /// Sends a `StreamStartMessage`
+    Resource {
+        name: String,
+        offset_samples: u64,
+        end_timestamp_us: Option<u64>,
+    },
+    /// Sends an arbitrary text message
+    Text(String),
+    /// Sends `count` Opus frames, waiting `interval` after each one
+    Frames {
+        count: usize,
+        interval: Duration,
+    },
+    /// Skips `count` frames of the signal without sending them
+    Drop(usize),
+    Delay(Duration),
+    /// Closes the websocket properly
+    Close,
+    /// Drops the connection without a close handshake
+    Disconnect,
+}
+
+impl Step {
+    pub fn resource(name: &str) -> Step {
+        Step::Resource {
+            name: name.into(),
+            offset_samples: 0,
+            end_timestamp_us: None,
+        }
+    }
+
+    pub fn frames(count: usize) -> Step {
+        Step::Frames {
+            count,
+            interval: Duration::from_millis(0),
+        }
+    }
+}
+
+/// Encodes a sine wave into Opus frames
+struct Signal {
+    encoder: Encoder,
+    phase: f32,
+}
+
+impl Signal {
+    fn new() -> Self {
+        Signal {
+            encoder: Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio)
+                .unwrap(),
+            phase: 0.0,
+        }
+    }
+
+    fn next_frame(&mut self) -> Vec<u8> {
+        let step = 440.0 * 2.0 * std::f32::consts::PI / SAMPLE_RATE as f32;
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME as usize * 2);
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = self.phase.sin() * 0.25;
+            samples.push(sample);
+            samples.push(sample);
+            self.phase = (self.phase + step) % (2.0 * std::f32::consts::PI);
+        }
+        let mut output = vec![0; 4000];
+        let len = self.encoder.encode_float(&samples, &mut output).unwrap();
+        output.truncate(len);
+        output
+    }
+}
+
+/// Opus frames of a sine wave, as sent by the server
+pub fn encoded_frames(count: usize) -> Vec<Vec<u8>> {
+    let mut signal = Signal::new();
+    (0..count).map(|_| signal.next_frame()).collect()
+}
+
+#[derive(Default)]
+struct Counters {
+    connections: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+pub struct MockServer {
+    address: String,
+    counters: Arc<Counters>,
+}
+
+impl MockServer {
+    /// Listens on a free local port, running `script` for every client
+    pub async fn start(script: Vec<Step>) -> MockServer {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("ws://{}/", listener.local_addr().unwrap());
+        let counters = Arc::new(Counters::default());
+        let script = Arc::new(script);
+        {
+            let counters = counters.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    counters.connections.fetch_add(1, AcqRel);
+                    let counters = counters.clone();
+                    let script = script.clone();
+                    tokio::spawn(async move {
+                        serve(stream, &script).await;
+                        counters.finished.fetch_add(1, AcqRel);
+                    });
+                }
+            });
+        }
+        MockServer { address, counters }
+    }
+
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.counters.connections.load(Acquire)
+    }
+
+    /// Number of connections that were closed by either side
+    pub fn finished(&self) -> usize {
+        self.counters.finished.load(Acquire)
+    }
+}
+
+async fn serve(stream: TcpStream, script: &[Step]) {
+    let mut socket = match accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    let mut signal = Signal::new();
+    for step in script {
+        let res = match step {
+            Step::Resource {
+                name,
+                offset_samples,
+                end_timestamp_us,
+            } => {
+                let message = serde_json::json!({
+                    "offset_samples": offset_samples,
+                    "start_timestamp_us": 0,
+                    "end_timestamp_us": end_timestamp_us,
+                    "duration_us": null,
+                    "name": name,
+                });
+                socket.send(Message::Text(message.to_string())).await
+            }
+            Step::Text(text) => socket.send(Message::Text(text.clone())).await,
+            Step::Frames { count, interval } => {
+                let mut res = Ok(());
+                for _ in 0..*count {
+                    res = socket.send(Message::Binary(signal.next_frame())).await;
+                    if res.is_err() {
+                        break;
+                    }
+                    if *interval > Duration::from_millis(0) {
+                        tokio::time::delay_for(*interval).await;
+                    }
+                }
+                res
+            }
+            Step::Drop(count) => {
+                for _ in 0..*count {
+                    signal.next_frame();
+                }
+                Ok(())
+            }
+            Step::Delay(duration) => {
+                tokio::time::delay_for(*duration).await;
+                Ok(())
+            }
+            Step::Close => {
+                let _ = socket.close(None).await;
+                return;
+            }
+            Step::Disconnect => return,
+        };
+        if res.is_err() {
+            return;
+        }
+    }
+
+    // Keep the connection open until the client closes it
+    while let Some(Ok(message)) = socket.next().await {
+        if message.is_close() {
+            break;
+        }
+    }
+}
+
+/// Returns the given chunks in order, `None` entries simulate the source falling behind
+pub struct ChunkSource(pub VecDeque<Option<Vec<f32>>>);
+
+impl Iterator for ChunkSource {
+    type Item = Vec<f32>;
+
+    fn next(&mut self) -> Option<Vec<f32>> {
+        self.0.pop_front().flatten()
+    }
+}
+
+impl AudioSource for ChunkSource {}
+
+/// A chunk of `frames` stereo frames with every sample set to `value`
+pub fn constant_chunk(frames: usize, value: f32) -> Vec<f32> {
+    vec![value; frames * 2]
+}
+
+/// Sends a single HTTP/1.0 request and returns the whole response including headers
+pub async fn http_request(address: SocketAddr, method: &str, path: &str, body: &str) -> String {
+    http_request_with_type(address, method, path, "application/json", body).await
+}
+
+pub async fn http_request_with_type(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    content_type: &str,
+    body: &str,
+) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.0\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Records a fixed number of frames into memory and optionally a WAV file
+pub struct CaptureSink {
+    sink: PacedSink,
+    frames: u64,
+    wav: Option<PathBuf>,
+    samples: Arc<Mutex<Vec<f32>>>,
+}
+
+impl CaptureSink {
+    pub fn new(pace: Pace, frames: u64) -> Self {
+        CaptureSink {
+            sink: PacedSink::new(pace),
+            frames,
+            wav: None,
+            samples: Default::default(),
+        }
+    }
+
+    /// Additionally writes the captured audio to `path`
+    pub fn with_wav(mut self, path: PathBuf) -> Self {
+        self.wav = Some(path);
+        self
+    }
+
+    /// Waits until all frames were captured and the WAV file is finished
+    pub fn wait(&mut self) -> io::Result<()> {
+        self.sink.wait()
+    }
+
+    /// Interleaved stereo samples captured so far, underruns are filled with silence
+    pub fn samples(&self) -> Vec<f32> {
+        self.samples.lock().unwrap().clone()
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.sink.underruns()
+    }
+}
+
+impl OutputSink for CaptureSink {
+    fn start(&mut self, reader: SourceReader) {
+        self.samples.lock().unwrap().clear();
+        let samples = self.samples.clone();
+        let mut wav = self.wav.clone().map(|path| (path, None));
+        let frames = self.frames;
+        self.sink.start(reader, Some(frames), move |block| {
+            samples.lock().unwrap().extend_from_slice(block);
+            if let Some((path, writer)) = wav.as_mut() {
+                if writer.is_none() {
+                    let file = BufWriter::new(File::create(path)?);
+                    *writer = Some(hound::WavWriter::new(file, wav_spec()).map_err(hound_error)?);
+                }
+                let writer = writer.as_mut().unwrap();
+                for &sample in block {
+                    writer.write_sample(sample).map_err(hound_error)?;
+                }
+                if writer.len() as u64 >= frames * 2 {
+                    writer.flush().map_err(hound_error)?;
+                }
+            }
+            Ok(())
+        });
+    }
+}
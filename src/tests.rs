@@ -0,0 +1,1232 @@
+//! End-to-end tests of `AudioSocket` and `AudioClient` against a `MockServer` and of the
+//! output sinks
+
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Release;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use imgui::TextureId;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::audio_client::{AudioClient, ResumeMode, Transition, TransitionMode, SAMPLES_PER_FRAME};
+use crate::audio_socket::{
+    parse_message, AudioMessage, AudioSocket, SocketToken, State, StreamStartMessage,
+};
+use crate::audio_stream::{AudioSource, OutputSink, SourceReader};
+use crate::control::{self, ControlApi, ControlCommand};
+use crate::effects::{effect_chain, GainControl};
+use crate::gfx_system::{ImageSource, TextureCache};
+use crate::gui::history::HistoryWindow;
+use crate::gui::shortcuts::Shortcut;
+use crate::gui::PlayerState;
+use crate::metrics::{self, MetricsSources};
+use crate::now_playing::{self, ResourceTime};
+use crate::output::{NullSink, Pace};
+use crate::playback_clock::PlaybackClock;
+use crate::recorder::Recorder;
+use crate::session::{SessionReader, SessionReplay, SessionWriter, SharedSessionWriter};
+use crate::settings::Settings;
+use crate::statistics::Statistics;
+use crate::test_support::{
+    constant_chunk, encoded_frames, http_request, http_request_with_type, CaptureSink, ChunkSource,
+    MockServer, Step,
+};
+use crate::token::{Cancelable, Completable};
+
+const FRAME_LEN: usize = SAMPLES_PER_FRAME as usize * 2;
+
+struct Connection {
+    socket: AudioSocket,
+    token: SocketToken,
+    state: Arc<Mutex<State>>,
+    statistics: Arc<Statistics>,
+    /// Kept alive so the `AudioClient` doesn't see a closed channel
+    sender: Sender<AudioMessage>,
+    receiver: Receiver<AudioMessage>,
+}
+
+fn connect(address: String) -> Connection {
+    connect_recording(address, None)
+}
+
+fn connect_recording(address: String, session: Option<SharedSessionWriter>) -> Connection {
+    let (sender, receiver) = channel(1000);
+    let token = SocketToken::default();
+    let state = Arc::new(Mutex::new(State::None));
+    let statistics = Arc::new(Statistics::default());
+    let socket = AudioSocket::new(
+        address,
+        token.clone(),
+        state.clone(),
+        sender.clone(),
+        statistics.clone(),
+        session,
+    );
+    Connection {
+        socket,
+        token,
+        statistics,
+        state,
+        sender,
+        receiver,
+    }
+}
+
+fn drain(receiver: &mut Receiver<AudioMessage>) -> Vec<AudioMessage> {
+    let mut messages = Vec::new();
+    while let Ok(message) = receiver.try_recv() {
+        messages.push(message);
+    }
+    messages
+}
+
+fn count_audio(messages: &[AudioMessage]) -> usize {
+    messages
+        .iter()
+        .filter(|m| matches!(m, AudioMessage::Audio(_)))
+        .count()
+}
+
+fn resource_names(messages: &[AudioMessage]) -> Vec<&str> {
+    messages
+        .iter()
+        .filter_map(|m| match m {
+            AudioMessage::NewResource(info) => Some(info.name.as_str()),
+            AudioMessage::Audio(_) => None,
+        })
+        .collect()
+}
+
+/// Runs the socket to completion and returns a client that received everything
+async fn play(script: Vec<Step>, transition: Transition) -> (Arc<PlayerState>, Vec<Vec<f32>>) {
+    let server = MockServer::start(script).await;
+    let connection = connect(server.address());
+    let state = Arc::new(PlayerState::new(transition));
+    state.set_target_buffer(5);
+    let (_recorder, tap) = Recorder::spawn();
+    let mut client = AudioClient::new(connection.receiver, state.clone(), tap);
+    connection.socket.run().await;
+
+    let frames = std::iter::from_fn(|| client.next()).collect();
+    drop(connection.sender);
+    (state, frames)
+}
+
+#[tokio::test]
+async fn socket_forwards_resources_and_audio() {
+    let server = MockServer::start(vec![
+        Step::resource("First"),
+        Step::frames(3),
+        Step::resource("Second"),
+        Step::frames(2),
+        Step::Close,
+    ])
+    .await;
+    let mut connection = connect(server.address());
+    connection.socket.run().await;
+
+    assert!(connection.token.is_completed());
+    let messages = drain(&mut connection.receiver);
+    assert_eq!(resource_names(&messages), vec!["First", "Second"]);
+    assert_eq!(count_audio(&messages), 5);
+    assert!(matches!(messages[0], AudioMessage::NewResource(_)));
+    assert_eq!(connection.statistics.packets(), 5);
+    let bytes: usize = messages
+        .iter()
+        .map(|m| match m {
+            AudioMessage::Audio(data) => data.len(),
+            AudioMessage::NewResource(_) => 0,
+        })
+        .sum();
+    assert_eq!(connection.statistics.bytes(), bytes as u64);
+}
+
+#[tokio::test]
+async fn socket_ignores_invalid_text_messages() {
+    let server = MockServer::start(vec![
+        Step::Text("not json".into()),
+        Step::resource("Valid"),
+        Step::Close,
+    ])
+    .await;
+    let mut connection = connect(server.address());
+    connection.socket.run().await;
+
+    let messages = drain(&mut connection.receiver);
+    assert_eq!(resource_names(&messages), vec!["Valid"]);
+}
+
+fn parse_resource(text: &str) -> StreamStartMessage {
+    match parse_message(tokio_tungstenite::tungstenite::Message::Text(text.into())) {
+        Some(AudioMessage::NewResource(message)) => message,
+        _ => panic!("Not parsed as resource: {}", text),
+    }
+}
+
+#[test]
+fn resource_metadata_is_optional() {
+    let message = parse_resource(
+        r#"{"offset_samples":0,"start_timestamp_us":0,"end_timestamp_us":null,"duration_us":null,"name":"Old"}"#,
+    );
+    assert_eq!(message.name, "Old");
+    assert!(message.metadata.artist.is_none());
+    assert!(message.metadata.thumbnail.is_none());
+    assert!(message.metadata.tags.is_empty());
+
+    let message = parse_resource(
+        r#"{"offset_samples":0,"start_timestamp_us":0,"end_timestamp_us":null,"duration_us":null,
+            "name":"New","artist":"Artist","album":"Album","source_url":"https://example.com/a",
+            "requester":"someone","thumbnail":"iVBORw==","tags":["live","jazz"]}"#,
+    );
+    let metadata = &message.metadata;
+    assert_eq!(metadata.artist.as_deref(), Some("Artist"));
+    assert_eq!(metadata.album.as_deref(), Some("Album"));
+    assert_eq!(
+        metadata.source_url.as_deref(),
+        Some("https://example.com/a")
+    );
+    assert_eq!(metadata.requester.as_deref(), Some("someone"));
+    assert_eq!(metadata.thumbnail.as_deref(), Some(&b"\x89PNG"[..]));
+    assert_eq!(metadata.tags, vec!["live", "jazz"]);
+
+    // A broken thumbnail doesn't drop the resource
+    let message = parse_resource(
+        r#"{"offset_samples":0,"start_timestamp_us":0,"end_timestamp_us":null,"duration_us":null,
+            "name":"Broken","thumbnail":"not base64!"}"#,
+    );
+    assert_eq!(message.name, "Broken");
+    assert!(message.metadata.thumbnail.is_none());
+}
+
+#[tokio::test]
+async fn socket_forwards_frames_around_drops() {
+    let server = MockServer::start(vec![
+        Step::resource("Lossy"),
+        Step::frames(4),
+        Step::Drop(10),
+        Step::Delay(Duration::from_millis(50)),
+        Step::frames(4),
+        Step::Close,
+    ])
+    .await;
+    let mut connection = connect(server.address());
+    connection.socket.run().await;
+
+    assert_eq!(count_audio(&drain(&mut connection.receiver)), 8);
+}
+
+#[tokio::test]
+async fn socket_survives_server_disconnect() {
+    let server = MockServer::start(vec![Step::frames(2), Step::Disconnect]).await;
+    let mut connection = connect(server.address());
+    connection.socket.run().await;
+
+    assert!(connection.token.is_completed());
+    assert_eq!(count_audio(&drain(&mut connection.receiver)), 2);
+}
+
+#[tokio::test]
+async fn socket_reports_failed_connection() {
+    // Reserve a port and free it again so nothing listens on it
+    let address = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("ws://{}/", listener.local_addr().unwrap())
+    };
+    let mut connection = connect(address);
+    connection.socket.run().await;
+
+    assert!(connection.token.is_completed());
+    assert!(drain(&mut connection.receiver).is_empty());
+}
+
+#[tokio::test]
+async fn cancel_closes_connection() {
+    let server = MockServer::start(vec![Step::Frames {
+        count: 1000,
+        interval: Duration::from_millis(5),
+    }])
+    .await;
+    let Connection {
+        socket,
+        token,
+        state,
+        mut receiver,
+        ..
+    } = connect(server.address());
+    let handle = tokio::spawn(socket.run());
+
+    // Wait for the first frame
+    assert!(matches!(
+        receiver.recv().await,
+        Some(AudioMessage::Audio(_))
+    ));
+    assert!(matches!(*state.lock().unwrap(), State::Connected));
+
+    token.cancel();
+    handle.await.unwrap();
+    assert!(token.is_completed());
+
+    for _ in 0..100 {
+        if server.finished() == 1 {
+            break;
+        }
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.connections(), 1);
+    assert_eq!(server.finished(), 1);
+}
+
+#[tokio::test]
+async fn client_decodes_stream() {
+    let (state, frames) = play(
+        vec![Step::resource("Song"), Step::frames(20), Step::Close],
+        Transition::default(),
+    )
+    .await;
+
+    assert_eq!(frames.len(), 20);
+    assert!(frames.iter().all(|frame| frame.len() == FRAME_LEN));
+    assert!(frames[10].iter().any(|sample| sample.abs() > 0.01));
+    assert_eq!(state.state().item.as_ref().unwrap().name, "Song");
+    assert_eq!(state.timestamp(), 20 * SAMPLES_PER_FRAME);
+    assert_eq!(state.buffer(), 0);
+    assert_eq!(state.statistics().decode_errors(), 0);
+    // The resource message and all frames arrive before playback starts
+    assert_eq!(state.statistics().buffer_range(), Some((0, 21)));
+}
+
+#[tokio::test]
+async fn client_waits_for_target_buffer() {
+    let (_, frames) = play(
+        vec![Step::resource("Short"), Step::frames(3), Step::Close],
+        Transition::default(),
+    )
+    .await;
+
+    assert!(frames.is_empty());
+}
+
+/// Pauses after everything arrived, then resumes and returns the state and the frames
+/// played after resuming
+async fn pause_and_resume(mode: ResumeMode) -> (Arc<PlayerState>, Vec<Vec<f32>>) {
+    let server =
+        MockServer::start(vec![Step::resource("Song"), Step::frames(20), Step::Close]).await;
+    let connection = connect(server.address());
+    let state = Arc::new(PlayerState::new(Transition::default()));
+    state.set_target_buffer(5);
+    state.set_max_pause_buffer(8);
+    let (_recorder, tap) = Recorder::spawn();
+    let mut client = AudioClient::new(connection.receiver, state.clone(), tap);
+    connection.socket.run().await;
+
+    state.pause();
+    let silence = client.next().unwrap();
+    assert_eq!(silence, vec![0.0; FRAME_LEN]);
+    // The resource message and the oldest twelve frames are dropped
+    assert_eq!(state.buffer(), 8);
+    assert_eq!(state.timestamp(), 12 * SAMPLES_PER_FRAME);
+    assert_eq!(state.state().item.as_ref().unwrap().name, "Song");
+
+    state.resume(mode);
+    assert!(!state.is_paused());
+    let frames = std::iter::from_fn(|| client.next()).collect();
+    drop(connection.sender);
+    (state, frames)
+}
+
+#[tokio::test]
+async fn client_resumes_time_shifted() {
+    let (state, frames) = pause_and_resume(ResumeMode::TimeShift).await;
+
+    assert_eq!(frames.len(), 8);
+    assert_eq!(state.timestamp(), 20 * SAMPLES_PER_FRAME);
+}
+
+#[tokio::test]
+async fn client_resumes_live() {
+    let (state, frames) = pause_and_resume(ResumeMode::Live).await;
+
+    // The backlog is dropped down to the target buffer
+    assert_eq!(frames.len(), 5);
+    assert_eq!(state.timestamp(), 20 * SAMPLES_PER_FRAME);
+}
+
+fn two_songs() -> Vec<Step> {
+    vec![
+        Step::resource("A"),
+        Step::frames(10),
+        Step::resource("B"),
+        Step::frames(10),
+        Step::Close,
+    ]
+}
+
+#[tokio::test]
+async fn client_fades_between_resources() {
+    let transition = Transition {
+        mode: TransitionMode::Fade,
+        duration_ms: 100,
+    };
+    let (state, frames) = play(two_songs(), transition).await;
+
+    let samples: usize = frames.iter().map(|frame| frame.len()).sum();
+    assert_eq!(samples, 20 * FRAME_LEN);
+    assert_eq!(state.state().item.as_ref().unwrap().name, "B");
+    assert_eq!(state.timestamp(), 10 * SAMPLES_PER_FRAME);
+}
+
+#[tokio::test]
+async fn client_crossfades_between_resources() {
+    let transition = Transition {
+        mode: TransitionMode::Crossfade,
+        duration_ms: 100,
+    };
+    let (state, frames) = play(two_songs(), transition).await;
+
+    // The last five frames of A overlap with the first five frames of B
+    let samples: usize = frames.iter().map(|frame| frame.len()).sum();
+    assert_eq!(samples, 15 * FRAME_LEN);
+    assert_eq!(state.state().item.as_ref().unwrap().name, "B");
+    assert_eq!(state.timestamp(), 10 * SAMPLES_PER_FRAME);
+}
+
+#[tokio::test]
+async fn client_rewinds_into_history() {
+    let server = MockServer::start(two_songs()).await;
+    let connection = connect(server.address());
+    let state = Arc::new(PlayerState::new(Transition::default()));
+    state.set_target_buffer(5);
+    let (_recorder, tap) = Recorder::spawn();
+    let mut client = AudioClient::new(connection.receiver, state.clone(), tap);
+    connection.socket.run().await;
+
+    for _ in 0..15 {
+        client.next().unwrap();
+    }
+    assert_eq!(state.state().item.as_ref().unwrap().name, "B");
+    assert_eq!(state.timestamp(), 5 * SAMPLES_PER_FRAME);
+
+    state.seek(SAMPLES_PER_FRAME);
+    client.next().unwrap();
+    assert_eq!(state.timestamp(), 2 * SAMPLES_PER_FRAME);
+    assert_eq!(state.state().item.as_ref().unwrap().name, "B");
+    assert_eq!(std::iter::from_fn(|| client.next()).count(), 8);
+
+    state.seek(0);
+    client.next();
+    assert_eq!(state.timestamp(), 0);
+    assert_eq!(state.behind_live(), 5);
+    // Seeking forward stops at the target buffer
+    state.seek(20 * SAMPLES_PER_FRAME);
+    client.next();
+    assert_eq!(state.timestamp(), 5 * SAMPLES_PER_FRAME);
+    drop(connection.sender);
+}
+
+#[tokio::test]
+async fn client_rewinds_across_resources_without_new_history() {
+    // B was joined 20 frames in, so seeking to its start rewinds into A
+    let server = MockServer::start(vec![
+        Step::resource("A"),
+        Step::frames(10),
+        Step::Resource {
+            name: "B".into(),
+            offset_samples: 20 * SAMPLES_PER_FRAME,
+            end_timestamp_us: None,
+        },
+        Step::frames(10),
+        Step::Close,
+    ])
+    .await;
+    let connection = connect(server.address());
+    let state = Arc::new(PlayerState::new(Transition::default()));
+    state.set_target_buffer(5);
+    let (_recorder, tap) = Recorder::spawn();
+    let mut client = AudioClient::new(connection.receiver, state.clone(), tap);
+    let mut history = HistoryWindow::new(state.clone());
+    connection.socket.run().await;
+
+    for _ in 0..15 {
+        client.next().unwrap();
+        history.update();
+    }
+    assert_eq!(history.names(), vec!["B", "A"]);
+    assert_eq!(state.resource_id(), 2);
+
+    state.seek(0);
+    client.next().unwrap();
+    history.update();
+    assert_eq!(state.state().item.as_ref().unwrap().name, "A");
+    while client.next().is_some() {
+        history.update();
+    }
+    assert_eq!(state.state().item.as_ref().unwrap().name, "B");
+    assert_eq!(history.names(), vec!["B", "A"]);
+    assert_eq!(state.resource_id(), 2);
+    drop(connection.sender);
+}
+
+#[test]
+fn session_reader_rejects_oversized_records() {
+    let mut data = b"LKSESS\x00\x01".to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(&u32::MAX.to_le_bytes());
+    let mut reader = SessionReader::new(std::io::Cursor::new(data)).unwrap();
+    match reader.next_record() {
+        Err(err) => assert_eq!(err.kind(), std::io::ErrorKind::InvalidData),
+        Ok(_) => panic!("Expected an oversized record to be rejected"),
+    }
+}
+
+#[tokio::test]
+async fn session_record_and_replay() {
+    let path = std::env::temp_dir().join(format!("leierkasten-session-{}", std::process::id()));
+    let server = MockServer::start(vec![
+        Step::resource("Recorded"),
+        Step::frames(3),
+        Step::Delay(Duration::from_millis(200)),
+        Step::frames(2),
+        Step::Close,
+    ])
+    .await;
+    let writer = Arc::new(Mutex::new(SessionWriter::create(&path).unwrap()));
+    let mut connection = connect_recording(server.address(), Some(writer));
+    connection.socket.run().await;
+    let recorded = drain(&mut connection.receiver);
+
+    let mut reader = SessionReader::open(&path).unwrap();
+    let mut times = Vec::new();
+    while let Some(record) = reader.next_record().unwrap() {
+        times.push(record.time);
+    }
+    assert_eq!(times.len(), 6);
+    assert_eq!(times[0], Duration::from_secs(0));
+    assert!(times[4] - times[3] >= Duration::from_millis(150));
+
+    let (sender, mut receiver) = channel(1000);
+    let token = SocketToken::default();
+    let state = Arc::new(Mutex::new(State::None));
+    let replay = SessionReplay::new(
+        path.clone(),
+        token.clone(),
+        state,
+        sender,
+        Default::default(),
+    );
+    let start = std::time::Instant::now();
+    replay.run().await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(token.is_completed());
+    assert!(start.elapsed() >= times[5]);
+    let replayed = drain(&mut receiver);
+    assert_eq!(resource_names(&replayed), resource_names(&recorded));
+    assert_eq!(count_audio(&replayed), 5);
+    let audio = |messages: &[AudioMessage]| -> Vec<Vec<u8>> {
+        messages
+            .iter()
+            .filter_map(|m| match m {
+                AudioMessage::Audio(data) => Some(data.clone()),
+                AudioMessage::NewResource(_) => None,
+            })
+            .collect()
+    };
+    assert_eq!(audio(&replayed), audio(&recorded));
+}
+
+#[tokio::test]
+async fn metrics_endpoint_reports_state() {
+    let player_state = Arc::new(PlayerState::new(Transition::default()));
+    player_state.set_buffer(50, 0);
+    player_state.statistics().add_underrun();
+    player_state.state().item = Some(StreamStartMessage {
+        offset_samples: 0,
+        start_timestamp_us: 0,
+        end_timestamp_us: None,
+        duration_us: None,
+        name: "Say \"hi\"".into(),
+        metadata: Default::default(),
+    });
+    let sources = MetricsSources {
+        player_state: player_state.clone(),
+        socket_state: Arc::new(Mutex::new(State::Connected)),
+    };
+    let address = metrics::start("127.0.0.1:0".parse().unwrap(), sources).unwrap();
+
+    let response = http_request(address, "GET", "/metrics", "").await;
+
+    assert!(response.starts_with("HTTP/1.0 200 OK"), "{}", response);
+    assert!(response.contains("leierkasten_connection_state{state=\"connected\"} 1\n"));
+    assert!(response.contains("leierkasten_connection_state{state=\"none\"} 0\n"));
+    assert!(response.contains("leierkasten_buffer_seconds 1\n"));
+    assert!(response.contains("leierkasten_underruns_total 1\n"));
+    assert!(response.contains("# TYPE leierkasten_reconnects_total counter\n"));
+    assert!(response.contains("leierkasten_resource_info{name=\"Say \\\"hi\\\"\"} 1\n"));
+}
+
+#[tokio::test]
+async fn control_api_commands_and_state() {
+    let (commands, receiver) = std::sync::mpsc::channel();
+    let player_state = Arc::new(PlayerState::new(Transition::default()));
+    player_state.set_timestamp(48000 * 3);
+    let api = ControlApi {
+        commands,
+        player_state: player_state.clone(),
+        socket_state: Arc::new(Mutex::new(State::None)),
+        volume: Arc::new(GainControl::new(0.0)),
+    };
+    let address = control::start("127.0.0.1:0".parse().unwrap(), api).unwrap();
+
+    let response = http_request(address, "GET", "/now_playing", "").await;
+    assert!(response.starts_with("HTTP/1.0 200 OK"), "{}", response);
+    let body = response.split("\r\n\r\n").nth(1).unwrap();
+    let state: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(state["connection"], "none");
+    assert_eq!(state["title"], serde_json::Value::Null);
+    assert_eq!(state["position_s"], 3.0);
+    assert_eq!(state["volume"], 1.0);
+    assert_eq!(state["target_buffer_ms"], 1000);
+    assert_eq!(state["paused"], false);
+
+    let response = http_request(address, "POST", "/connect", "").await;
+    assert!(response.starts_with("HTTP/1.0 202"), "{}", response);
+    assert!(matches!(receiver.try_recv(), Ok(ControlCommand::Connect)));
+
+    // Cross-site form posts can't set a JSON content type
+    let response = http_request_with_type(address, "POST", "/disconnect", "text/plain", "").await;
+    assert!(response.starts_with("HTTP/1.0 415"), "{}", response);
+
+    let response = http_request(address, "POST", "/pause", "").await;
+    assert!(response.starts_with("HTTP/1.0 202"), "{}", response);
+    assert!(matches!(receiver.try_recv(), Ok(ControlCommand::Pause)));
+
+    let response = http_request(address, "PUT", "/volume", r#"{"volume": 0.25}"#).await;
+    assert!(response.starts_with("HTTP/1.0 202"), "{}", response);
+    match receiver.try_recv() {
+        Ok(ControlCommand::SetVolume(volume)) => assert_eq!(volume, 0.25),
+        _ => panic!("Expected a volume command"),
+    }
+
+    let response = http_request(address, "PUT", "/volume", r#"{"volume": 2}"#).await;
+    assert!(response.starts_with("HTTP/1.0 400"), "{}", response);
+
+    let response = http_request(
+        address,
+        "PUT",
+        "/target_buffer",
+        r#"{"target_buffer_ms": 200}"#,
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+    assert_eq!(player_state.target_buffer(), 10);
+
+    for target_buffer_ms in &["0", "10001", "18446744073709551615"] {
+        let body = format!(r#"{{"target_buffer_ms": {}}}"#, target_buffer_ms);
+        let response = http_request(address, "PUT", "/target_buffer", &body).await;
+        assert!(response.starts_with("HTTP/1.0 400"), "{}", response);
+    }
+    assert_eq!(player_state.target_buffer(), 10);
+
+    // Refused by the announced length, before reading the body
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let request = "PUT /volume HTTP/1.0\r\nContent-Type: application/json\r\n\
+                   Content-Length: 1000000\r\n\r\n";
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.0 413"), "{}", response);
+
+    let response = http_request(address, "GET", "/unknown", "").await;
+    assert!(response.starts_with("HTTP/1.0 404"), "{}", response);
+    assert!(receiver.try_recv().is_err());
+}
+
+/// Runs the MPRIS service on a private bus, needs `dbus-daemon`
+#[cfg(target_os = "linux")]
+#[test]
+#[ignore]
+fn mpris_service_on_private_bus() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    use dbus::arg::PropMap;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::blocking::Connection;
+    use dbus::channel::Channel;
+
+    use crate::mpris::{Mpris, MprisState};
+
+    const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+    let mut daemon = match Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(daemon) => daemon,
+        Err(err) => panic!("Failed to start dbus-daemon: {}", err),
+    };
+    let mut address = String::new();
+    BufReader::new(daemon.stdout.take().unwrap())
+        .read_line(&mut address)
+        .unwrap();
+    let address = address.trim().to_owned();
+
+    let (commands, receiver) = std::sync::mpsc::channel();
+    let player_state = Arc::new(PlayerState::new(Transition::default()));
+    let socket_state = Arc::new(Mutex::new(State::None));
+    let mpris = Mpris::spawn_on(
+        address.clone(),
+        MprisState {
+            player_state: player_state.clone(),
+            socket_state: socket_state.clone(),
+            commands,
+            volume: Arc::new(GainControl::new(0.0)),
+        },
+    );
+
+    let mut channel = Channel::open_private(&address).unwrap();
+    channel.register().unwrap();
+    let connection = Connection::from(channel);
+    let proxy = connection.with_proxy(
+        "org.mpris.MediaPlayer2.leierkasten",
+        "/org/mpris/MediaPlayer2",
+        Duration::from_secs(5),
+    );
+    // Wait until the service owns its name
+    let mut status = Err(dbus::Error::new_failed("Not started"));
+    for _ in 0..100 {
+        status = proxy.get::<String>(PLAYER, "PlaybackStatus");
+        if status.is_ok() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(status.unwrap(), "Stopped");
+
+    *socket_state.lock().unwrap() = State::Connected;
+    player_state.start_resource();
+    player_state.state().item = Some(StreamStartMessage {
+        offset_samples: 0,
+        start_timestamp_us: 0,
+        end_timestamp_us: Some(180 * 1000000),
+        duration_us: None,
+        name: "Song".into(),
+        metadata: Default::default(),
+    });
+    player_state.set_timestamp(48000);
+    assert_eq!(
+        proxy.get::<String>(PLAYER, "PlaybackStatus").unwrap(),
+        "Playing"
+    );
+    let metadata: PropMap = proxy.get(PLAYER, "Metadata").unwrap();
+    assert_eq!(metadata["xesam:title"].0.as_str(), Some("Song"));
+    assert_eq!(
+        metadata["mpris:trackid"].0.as_str(),
+        Some("/org/leierkasten/track/1")
+    );
+    assert_eq!(metadata["mpris:length"].0.as_i64(), Some(180 * 1000000));
+    assert_eq!(proxy.get::<i64>(PLAYER, "Position").unwrap(), 1000000);
+
+    let () = proxy.method_call(PLAYER, "PlayPause", ()).unwrap();
+    assert!(matches!(receiver.try_recv(), Ok(ControlCommand::Pause)));
+    player_state.pause();
+    assert_eq!(
+        proxy.get::<String>(PLAYER, "PlaybackStatus").unwrap(),
+        "Paused"
+    );
+    let () = proxy.method_call(PLAYER, "PlayPause", ()).unwrap();
+    assert!(matches!(receiver.try_recv(), Ok(ControlCommand::Resume)));
+    player_state.clear_pause();
+    let () = proxy.method_call(PLAYER, "Stop", ()).unwrap();
+    assert!(matches!(
+        receiver.try_recv(),
+        Ok(ControlCommand::Disconnect)
+    ));
+    *socket_state.lock().unwrap() = State::None;
+    let () = proxy.method_call(PLAYER, "Play", ()).unwrap();
+    assert!(matches!(receiver.try_recv(), Ok(ControlCommand::Connect)));
+    proxy.set(PLAYER, "Volume", 0.5f64).unwrap();
+    match receiver.try_recv() {
+        Ok(ControlCommand::SetVolume(volume)) => assert_eq!(volume, 0.5),
+        _ => panic!("Expected a volume command"),
+    }
+
+    drop(mpris);
+    let _ = daemon.kill();
+    let _ = daemon.wait();
+}
+
+fn chunk_reader(chunks: Vec<Option<Vec<f32>>>) -> SourceReader {
+    let (effects, _) = effect_chain(Arc::new(GainControl::new(0.0)));
+    SourceReader::new(ChunkSource(chunks.into()), effects, Default::default())
+}
+
+#[test]
+fn capture_collects_source_output() {
+    let mut sink = CaptureSink::new(Pace::Unlimited, 2000);
+    sink.start(chunk_reader(vec![
+        Some(constant_chunk(960, 0.5)),
+        Some(constant_chunk(960, -0.5)),
+        Some(constant_chunk(960, 0.25)),
+    ]));
+    sink.wait().unwrap();
+
+    let samples = sink.samples();
+    assert_eq!(samples.len(), 4000);
+    assert!(samples[..1920].iter().all(|&s| s == 0.5));
+    assert!(samples[1920..3840].iter().all(|&s| s == -0.5));
+    assert!(samples[3840..].iter().all(|&s| s == 0.25));
+    assert_eq!(sink.underruns(), 0);
+}
+
+#[test]
+fn capture_fills_underruns_with_silence() {
+    let mut sink = CaptureSink::new(Pace::Unlimited, 480 * 4);
+    sink.start(chunk_reader(vec![
+        Some(constant_chunk(480, 1.0)),
+        None,
+        None,
+        Some(constant_chunk(480, 1.0)),
+        None,
+    ]));
+    sink.wait().unwrap();
+
+    let samples = sink.samples();
+    let blocks: Vec<_> = samples.chunks(960).collect();
+    assert_eq!(blocks.len(), 4);
+    assert!(blocks[0].iter().all(|&s| s == 1.0));
+    // Consecutive blocks without data count as a single underrun
+    assert!(blocks[1].iter().all(|&s| s == 0.0));
+    assert!(blocks[2].iter().all(|&s| s == 0.0));
+    assert!(blocks[3].iter().all(|&s| s == 1.0));
+    assert_eq!(sink.underruns(), 1);
+}
+
+#[test]
+fn capture_writes_wav() {
+    let path = std::env::temp_dir().join(format!("leierkasten-capture-{}.wav", std::process::id()));
+    let mut sink = CaptureSink::new(Pace::Unlimited, 960).with_wav(path.clone());
+    sink.start(chunk_reader(vec![Some(constant_chunk(960, 0.125))]));
+    sink.wait().unwrap();
+
+    let mut reader = hound::WavReader::open(&path).unwrap();
+    let spec = reader.spec();
+    let samples: Vec<f32> = reader.samples::<f32>().map(Result::unwrap).collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(spec.channels, 2);
+    assert_eq!(spec.sample_rate, 48000);
+    assert_eq!(samples, constant_chunk(960, 0.125));
+}
+
+#[test]
+fn null_sink_paces_output() {
+    let chunks = std::iter::repeat_with(|| Some(constant_chunk(960, 0.0)))
+        .take(1000)
+        .collect();
+    let mut sink = NullSink::new(Pace::Accelerated(10.0));
+    sink.start(chunk_reader(chunks));
+    std::thread::sleep(Duration::from_millis(100));
+    sink.stop();
+
+    // 100 ms at ten times real time are about 48000 frames
+    let frames = sink.frames();
+    assert!(frames > 4800, "{} frames", frames);
+    assert!(frames < 96000, "{} frames", frames);
+    assert_eq!(sink.underruns(), 0);
+}
+
+#[test]
+fn texture_cache_evicts_least_recently_used() {
+    let sources = (0..3)
+        .map(|i| ImageSource::File(format!("cover{}.png", i).into()))
+        .collect::<Vec<_>>();
+    let mut cache = TextureCache::new(2);
+    let load = |id| move || Some(TextureId::from(id));
+    assert_eq!(
+        cache.get_or_load(&sources[0], load(0)),
+        (Some(0.into()), None)
+    );
+    cache.next_frame();
+    assert_eq!(
+        cache.get_or_load(&sources[1], load(1)),
+        (Some(1.into()), None)
+    );
+    cache.next_frame();
+    // Hits don't load again and keep the entry
+    assert_eq!(
+        cache.get_or_load(&sources[0], load(9)),
+        (Some(0.into()), None)
+    );
+    cache.next_frame();
+    assert_eq!(
+        cache.get_or_load(&sources[2], load(2)),
+        (Some(2.into()), Some(1.into()))
+    );
+    // Same data by allocation, failed loads are cached as well
+    let data: Arc<[u8]> = vec![1, 2, 3].into();
+    let mut cache = TextureCache::new(2);
+    assert_eq!(
+        cache.get_or_load(&ImageSource::Data(data.clone()), || None),
+        (None, None)
+    );
+    assert_eq!(
+        cache.get_or_load(&ImageSource::Data(data), load(1)),
+        (None, None)
+    );
+    // Textures drawn in the current frame stay, the cache grows instead
+    let mut cache = TextureCache::new(1);
+    cache.get_or_load(&sources[0], load(0));
+    assert_eq!(
+        cache.get_or_load(&sources[1], load(1)),
+        (Some(1.into()), None)
+    );
+}
+
+#[test]
+fn playback_clock_interpolates_heard_position() {
+    let clock = PlaybackClock::default();
+    let now = Instant::now();
+    let ms = Duration::from_millis;
+    assert_eq!(clock.position(now), None);
+    // 20 ms of audio heard after 100 ms of latency, then 20 ms of pause
+    clock.push(now, now + ms(100), 960, 1920, 960);
+    clock.push(now, now + ms(120), 1920, 1920, 960);
+    assert_eq!(clock.position(now), Some(960));
+    assert_eq!(clock.position(now + ms(110)), Some(1440));
+    assert_eq!(clock.position(now + ms(130)), Some(1920));
+    // Without more output the clock stops
+    assert_eq!(clock.position(now + ms(500)), Some(1920));
+    clock.reset();
+    assert_eq!(clock.position(now), None);
+}
+
+/// Frames of 960 stereo samples at the given source timestamps
+struct TimedSource(VecDeque<u64>, u64);
+
+impl Iterator for TimedSource {
+    type Item = Vec<f32>;
+
+    fn next(&mut self) -> Option<Vec<f32>> {
+        self.1 = self.0.pop_front()?;
+        Some(constant_chunk(960, 0.0))
+    }
+}
+
+impl AudioSource for TimedSource {
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.1)
+    }
+}
+
+#[test]
+fn source_reader_feeds_clock() {
+    let clock = Arc::new(PlaybackClock::default());
+    let (effects, _) = effect_chain(Arc::new(GainControl::new(0.0)));
+    // Two frames, a paused frame and a jump back
+    let source = TimedSource(vec![960, 1920, 1920, 960].into(), 0);
+    let mut reader =
+        SourceReader::new(source, effects, Default::default()).with_clock(clock.clone());
+    reader.set_latency(Duration::from_secs(1));
+    let mut buffer = vec![0.0; 960];
+    let start = Instant::now();
+    let mut positions = Vec::new();
+    for _ in 0..8 {
+        reader.fill(&mut buffer);
+        // Long after the output, each buffer is heard for 10 ms
+        positions.push(clock.position(start + Duration::from_secs(60)).unwrap());
+    }
+    assert_eq!(positions, vec![480, 960, 1440, 1920, 1920, 1920, 480, 960]);
+}
+
+fn resource(
+    offset_samples: u64,
+    start_timestamp_us: u64,
+    end_timestamp_us: Option<u64>,
+    duration_us: Option<u64>,
+) -> StreamStartMessage {
+    StreamStartMessage {
+        offset_samples,
+        start_timestamp_us,
+        end_timestamp_us,
+        duration_us,
+        name: "Resource".into(),
+        metadata: Default::default(),
+    }
+}
+
+#[test]
+fn resource_time_from_start_of_resource() {
+    // Starts 60 s into the file and ends at 3 minutes
+    let time = ResourceTime::new(&resource(0, 60_000_000, Some(180_000_000), None));
+    assert_eq!(time.duration_us, Some(120_000_000));
+    assert_eq!(time.position_us(48000 * 30), 30_000_000);
+    assert_eq!(time.progress(48000 * 30), Some(0.25));
+    assert_eq!(time.timestamp_at(30_000_000), 48000 * 30);
+}
+
+#[test]
+fn resource_time_honours_duration_limit() {
+    let time = ResourceTime::new(&resource(
+        0,
+        60_000_000,
+        Some(180_000_000),
+        Some(90_000_000),
+    ));
+    assert_eq!(time.duration_us, Some(90_000_000));
+    // Positions and seeks stay within the limit
+    assert_eq!(time.position_us(48000 * 100), 90_000_000);
+    assert_eq!(time.progress(48000 * 100), Some(1.0));
+    assert_eq!(time.timestamp_at(100_000_000), 48000 * 90);
+
+    // The limit alone bounds open ended resources
+    let time = ResourceTime::new(&resource(0, 0, None, Some(10_000_000)));
+    assert_eq!(time.duration_us, Some(10_000_000));
+    let time = ResourceTime::new(&resource(0, 0, None, None));
+    assert_eq!(time.duration_us, None);
+    assert_eq!(time.progress(48000), None);
+    assert_eq!(time.position_us(48000 * 1000), 1_000_000_000);
+}
+
+#[test]
+fn resource_time_starts_at_offset() {
+    // Joined 10 s into the resource, earlier audio was never received
+    let time = ResourceTime::new(&resource(48000 * 10, 0, Some(60_000_000), None));
+    assert_eq!(time.offset_us, 10_000_000);
+    assert_eq!(time.position_us(0), 10_000_000);
+    assert_eq!(time.position_us(48000 * 15), 15_000_000);
+    assert_eq!(time.timestamp_at(0), 48000 * 10);
+    assert_eq!(now_playing::position_us(None, 48000 * 15), 15_000_000);
+}
+
+#[test]
+fn settings_clamp_zoom_and_font_size() {
+    let settings: Settings =
+        serde_json::from_str(r#"{"zoom": 0.0, "font": {"font": "Roboto", "size": -3.0}}"#).unwrap();
+    let settings = settings.validated();
+    assert_eq!(settings.zoom, 0.5);
+    assert_eq!(settings.font.size, 8.0);
+
+    let settings: Settings = serde_json::from_str(r#"{"zoom": 100.0}"#).unwrap();
+    assert_eq!(settings.validated().zoom, 3.0);
+}
+
+#[test]
+fn statistics_reset_keeps_counters() {
+    let statistics = Statistics::default();
+    statistics.add_packet(100);
+    statistics.add_underrun();
+    statistics.update_buffer(10);
+    let baseline = statistics.counters();
+
+    statistics.reset_measurements();
+    statistics.add_packet(50);
+    assert_eq!(statistics.buffer_range(), None);
+    // Exported counters keep counting, the view starts from the baseline
+    assert_eq!(statistics.packets(), 2);
+    assert_eq!(statistics.underruns(), 1);
+    let counters = statistics.counters().since(&baseline);
+    assert_eq!(counters.packets, 1);
+    assert_eq!(counters.bytes, 50);
+    assert_eq!(counters.underruns, 0);
+}
+
+#[test]
+fn client_reports_buffering_until_first_frames() {
+    let state = Arc::new(PlayerState::new(Transition::default()));
+    let (_sender, receiver) = channel(10);
+    let (_recorder, tap) = Recorder::spawn();
+    let _client = AudioClient::new(receiver, state.clone(), tap);
+
+    std::thread::sleep(Duration::from_millis(5));
+    assert!(state.state().buffering);
+    assert!(state.statistics().buffering_time() >= Duration::from_millis(5));
+}
+
+#[test]
+fn gain_effects_have_their_own_controls() {
+    use crate::effects::{EffectConfig, EffectControls, EffectKind};
+    use crate::equalizer::EqualizerControl;
+
+    let (mut chain, mut handle) = effect_chain(Arc::new(GainControl::new(0.0)));
+    let controls = EffectControls {
+        equalizer: Arc::new(EqualizerControl::new(Default::default())),
+        volume: Arc::new(GainControl::new(0.0)),
+    };
+    let mut ids = Vec::new();
+    for gain_db in [-20.0, 20.0].iter() {
+        let config = EffectConfig {
+            gain_db: *gain_db,
+            ..EffectConfig::new(EffectKind::Gain)
+        };
+        let id = handle.insert(ids.len(), controls.create(&config).effect);
+        handle.set_enabled(id, true);
+        ids.push(id);
+    }
+
+    let mut samples = vec![0.5; 64];
+    // The first chunk fades the effects in
+    chain.process(&mut samples);
+    let mut samples = vec![0.5; 64];
+    chain.process(&mut samples);
+    assert!(samples.iter().all(|sample| (sample - 0.5).abs() < 1e-4));
+
+    handle.remove(ids[1]);
+    let mut samples = vec![0.5; 64];
+    chain.process(&mut samples);
+    let mut samples = vec![0.5; 64];
+    chain.process(&mut samples);
+    assert!(samples.iter().all(|sample| (sample - 0.05).abs() < 1e-4));
+    handle.drop_removed();
+}
+
+#[test]
+fn effect_chain_stays_within_its_capacity() {
+    use crate::effects::{EffectConfig, EffectControls, EffectKind, MAX_EFFECTS};
+    use crate::equalizer::EqualizerControl;
+
+    let settings = Settings {
+        effects: vec![EffectConfig::new(EffectKind::Gain); MAX_EFFECTS + 5],
+        ..Default::default()
+    };
+    assert_eq!(settings.validated().effects.len(), MAX_EFFECTS);
+
+    // Inserts beyond the preallocated slots are handed back instead of growing the chain
+    let (mut chain, mut handle) = effect_chain(Arc::new(GainControl::new(0.0)));
+    let controls = EffectControls {
+        equalizer: Arc::new(EqualizerControl::new(Default::default())),
+        volume: Arc::new(GainControl::new(0.0)),
+    };
+    let config = EffectConfig {
+        gain_db: 0.5,
+        ..EffectConfig::new(EffectKind::Gain)
+    };
+    for i in 0..MAX_EFFECTS * 3 {
+        let id = handle.insert(i, controls.create(&config).effect);
+        handle.set_enabled(id, true);
+    }
+    let mut samples = vec![0.5; 64];
+    chain.process(&mut samples);
+    let mut samples = vec![0.5; 64];
+    chain.process(&mut samples);
+    let expected = 0.5 * 10f32.powf(MAX_EFFECTS as f32 * 2.0 * 0.5 / 20.0);
+    assert!(samples
+        .iter()
+        .all(|sample| (sample - expected).abs() < 1e-3));
+    handle.drop_removed();
+}
+
+#[test]
+fn settings_limit_equalizer_bands() {
+    use crate::equalizer::{Band, BandKind, EqualizerPreset, EqualizerSettings, MAX_BANDS};
+
+    let bands = vec![Band::new(BandKind::Peaking, 1000.0, 1.0); MAX_BANDS + 1];
+    let settings = Settings {
+        equalizer: EqualizerSettings {
+            bands: bands.clone(),
+        },
+        equalizer_presets: vec![EqualizerPreset {
+            name: "Many".into(),
+            bands,
+        }],
+        ..Default::default()
+    };
+    let settings = settings.validated();
+    assert_eq!(settings.equalizer.bands.len(), MAX_BANDS);
+    assert_eq!(settings.equalizer_presets[0].bands.len(), MAX_BANDS);
+}
+
+#[test]
+fn ogg_seek_reports_the_page_start() {
+    use crate::ogg_opus::{OggOpusReader, OggOpusWriter};
+
+    let mut data = Vec::new();
+    let mut writer = OggOpusWriter::new(&mut data, "Seek").unwrap();
+    for frame in encoded_frames(500) {
+        writer.write_packet(frame).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut reader = OggOpusReader::new(std::io::Cursor::new(data)).unwrap();
+    assert_eq!(reader.duration(), 500 * SAMPLES_PER_FRAME);
+    let target = 300 * SAMPLES_PER_FRAME + 100;
+    let start = reader.seek(target).unwrap();
+    // Decoding starts at a packet on the page boundary before the target
+    assert!(start <= target);
+    assert_eq!(start % SAMPLES_PER_FRAME, 0);
+    let mut packets = 0;
+    while reader.next_packet().unwrap().is_some() {
+        packets += 1;
+    }
+    assert_eq!(start + packets * SAMPLES_PER_FRAME, 500 * SAMPLES_PER_FRAME);
+}
+
+#[derive(Clone, Default)]
+struct ShownNotifications(Arc<Mutex<Vec<String>>>);
+
+impl crate::notifications::ShowNotification for ShownNotifications {
+    fn show(&mut self, summary: &str, _body: &str) {
+        self.0.lock().unwrap().push(summary.to_owned());
+    }
+}
+
+#[test]
+fn notifications_are_rate_limited_and_suppressed_while_focused() {
+    use crate::notifications::{NotificationSettings, Notifier};
+
+    let shown = ShownNotifications::default();
+    let focused = Arc::new(AtomicBool::new(true));
+    let settings = NotificationSettings {
+        enabled: true,
+        when_focused: false,
+        min_interval_s: 1,
+    };
+    let (notifier, tap) = Notifier::spawn_with(settings, focused.clone(), shown.clone());
+    let track = |name: &str, duration_us: Option<u64>| StreamStartMessage {
+        name: name.into(),
+        ..resource(0, 0, None, duration_us)
+    };
+    let wait = || std::thread::sleep(Duration::from_millis(100));
+
+    // Nothing is shown while the client has focus
+    tap.track_changed(&track("Focused", None));
+    wait();
+    assert!(shown.0.lock().unwrap().is_empty());
+
+    focused.store(false, Release);
+    tap.track_changed(&track("First", None));
+    wait();
+    // Held back by the rate limit, and finished before it ends
+    tap.track_changed(&track("Short", Some(200_000)));
+    wait();
+    assert_eq!(*shown.0.lock().unwrap(), vec!["First"]);
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(*shown.0.lock().unwrap(), vec!["First"]);
+
+    tap.track_changed(&track("Second", None));
+    tap.track_changed(&track("Third", None));
+    wait();
+    // The change right after is held back until the interval passed
+    assert_eq!(*shown.0.lock().unwrap(), vec!["First", "Second"]);
+    std::thread::sleep(Duration::from_millis(1200));
+    assert_eq!(*shown.0.lock().unwrap(), vec!["First", "Second", "Third"]);
+    notifier.shutdown();
+}
+
+#[test]
+fn shortcuts_parse_key_names_and_modifiers() {
+    assert!(Shortcut::parse("Space").is_some());
+    assert!(Shortcut::parse("ctrl + d") == Shortcut::parse("Ctrl+D"));
+    assert!(Shortcut::parse("Shift+Alt+F1") == Shortcut::parse("alt+shift+f1"));
+    assert!(Shortcut::parse("Space") != Shortcut::parse("Shift+Space"));
+    assert!(Shortcut::parse("Ctrl+D") != Shortcut::parse("Ctrl+E"));
+
+    assert!(Shortcut::parse("").is_none());
+    assert!(Shortcut::parse("Ctrl+").is_none());
+    assert!(Shortcut::parse("Hyper+A").is_none());
+    assert!(Shortcut::parse("Ctrl+Backspace").is_none());
+}
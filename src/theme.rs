@@ -0,0 +1,185 @@
+//! Color themes, built in or loaded from JSON files in the `themes` config directory
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+use imgui::{Style, StyleColor};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::config_dir;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BaseColors {
+    Dark,
+    Light,
+    Classic,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Theme {
+    pub name: String,
+    pub base: BaseColors,
+    /// Colors replacing the base colors by `StyleColor` name, in sRGB
+    #[serde(default)]
+    pub colors: BTreeMap<String, [f32; 4]>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            name: "Dark".into(),
+            base: BaseColors::Dark,
+            colors: BTreeMap::new(),
+        }
+    }
+}
+
+/// Name used for a color in theme files
+pub fn color_name(color: StyleColor) -> String {
+    format!("{:?}", color)
+}
+
+// Fix incorrect colors with sRGB framebuffer
+fn gamma_to_linear(col: [f32; 4]) -> [f32; 4] {
+    let x = col[0].powf(2.2);
+    let y = col[1].powf(2.2);
+    let z = col[2].powf(2.2);
+    let w = 1.0 - (1.0 - col[3]).powf(2.2);
+    [x, y, z, w]
+}
+
+/// Inverse of `gamma_to_linear`, for showing the colors of the current style
+pub fn linear_to_gamma(col: [f32; 4]) -> [f32; 4] {
+    let x = col[0].powf(1.0 / 2.2);
+    let y = col[1].powf(1.0 / 2.2);
+    let z = col[2].powf(1.0 / 2.2);
+    let w = 1.0 - (1.0 - col[3]).powf(1.0 / 2.2);
+    [x, y, z, w]
+}
+
+fn themes_dir() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("themes"))
+}
+
+impl Theme {
+    fn high_contrast() -> Self {
+        let colors = [
+            (StyleColor::Text, [1.0, 1.0, 1.0, 1.0]),
+            (StyleColor::TextDisabled, [0.7, 0.7, 0.7, 1.0]),
+            (StyleColor::WindowBg, [0.0, 0.0, 0.0, 1.0]),
+            (StyleColor::PopupBg, [0.0, 0.0, 0.0, 1.0]),
+            (StyleColor::Border, [1.0, 1.0, 1.0, 1.0]),
+            (StyleColor::FrameBg, [0.0, 0.0, 0.0, 1.0]),
+            (StyleColor::FrameBgHovered, [0.2, 0.2, 0.0, 1.0]),
+            (StyleColor::FrameBgActive, [0.4, 0.4, 0.0, 1.0]),
+            (StyleColor::TitleBg, [0.0, 0.0, 0.0, 1.0]),
+            (StyleColor::TitleBgActive, [0.0, 0.0, 0.6, 1.0]),
+            (StyleColor::MenuBarBg, [0.0, 0.0, 0.0, 1.0]),
+            (StyleColor::CheckMark, [1.0, 1.0, 0.0, 1.0]),
+            (StyleColor::SliderGrab, [1.0, 1.0, 0.0, 1.0]),
+            (StyleColor::SliderGrabActive, [1.0, 1.0, 1.0, 1.0]),
+            (StyleColor::Button, [0.0, 0.0, 0.6, 1.0]),
+            (StyleColor::ButtonHovered, [0.0, 0.0, 0.9, 1.0]),
+            (StyleColor::ButtonActive, [1.0, 1.0, 0.0, 1.0]),
+            (StyleColor::Header, [0.0, 0.0, 0.6, 1.0]),
+            (StyleColor::HeaderHovered, [0.0, 0.0, 0.9, 1.0]),
+            (StyleColor::PlotLines, [1.0, 1.0, 0.0, 1.0]),
+            (StyleColor::PlotHistogram, [1.0, 1.0, 0.0, 1.0]),
+        ];
+        Theme {
+            name: "High contrast".into(),
+            base: BaseColors::Dark,
+            colors: colors
+                .iter()
+                .map(|(color, value)| (color_name(*color), *value))
+                .collect(),
+        }
+    }
+
+    pub fn builtin() -> Vec<Theme> {
+        vec![
+            Theme::default(),
+            Theme {
+                name: "Light".into(),
+                base: BaseColors::Light,
+                colors: BTreeMap::new(),
+            },
+            Theme::high_contrast(),
+        ]
+    }
+
+    /// Built-in themes followed by the theme files, files replace built-in themes of the same
+    /// name
+    pub fn load_all() -> Vec<Theme> {
+        let mut themes = Theme::builtin();
+        let entries = match themes_dir().map(std::fs::read_dir) {
+            Some(Ok(entries)) => entries,
+            _ => return themes,
+        };
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension() == Some(OsStr::new("json")))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let theme = File::open(&path)
+                .map_err(serde_json::Error::io)
+                .and_then(|file| serde_json::from_reader::<_, Theme>(BufReader::new(file)));
+            match theme {
+                Ok(theme) => match themes.iter_mut().find(|t| t.name == theme.name) {
+                    Some(existing) => *existing = theme,
+                    None => themes.push(theme),
+                },
+                Err(err) => warn!("Failed to load theme {}: {}", path.display(), err),
+            }
+        }
+        themes
+    }
+
+    /// Writes the theme to the themes directory, named after the theme
+    pub fn save(&self) {
+        let dir = match themes_dir() {
+            Some(dir) => dir,
+            None => {
+                warn!("No config directory, not saving theme");
+                return;
+            }
+        };
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            warn!("Failed to create {}: {}", dir.display(), err);
+            return;
+        }
+        let file_name: String = self
+            .name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let path = dir.join(file_name + ".json");
+        let res = File::create(&path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(BufWriter::new(file), self));
+        if let Err(err) = res {
+            warn!("Failed to save {}: {}", path.display(), err);
+        }
+    }
+
+    /// Sets the style colors, corrected for the sRGB framebuffer
+    pub fn apply(&self, style: &mut Style) {
+        match self.base {
+            BaseColors::Dark => style.use_dark_colors(),
+            BaseColors::Light => style.use_light_colors(),
+            BaseColors::Classic => style.use_classic_colors(),
+        };
+        for color in StyleColor::VARIANTS.iter() {
+            if let Some(value) = self.colors.get(&color_name(*color)) {
+                style[*color] = *value;
+            }
+        }
+        for col in 0..style.colors.len() {
+            style.colors[col] = gamma_to_linear(style.colors[col]);
+        }
+    }
+}